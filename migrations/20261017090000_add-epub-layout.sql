@@ -0,0 +1 @@
+ALTER TABLE Export ADD COLUMN epub_layout TEXT NOT NULL DEFAULT 'reflowable';
@@ -0,0 +1 @@
+ALTER TABLE Export ADD COLUMN reading_direction TEXT NOT NULL DEFAULT 'left_to_right';
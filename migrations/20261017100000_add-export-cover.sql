@@ -0,0 +1,4 @@
+ALTER TABLE Export ADD COLUMN cover_source TEXT NOT NULL DEFAULT 'thumbnail';
+ALTER TABLE Export ADD COLUMN cover_chapter_id INTEGER;
+ALTER TABLE Export ADD COLUMN cover_page INTEGER;
+ALTER TABLE Export ADD COLUMN cover_path TEXT;
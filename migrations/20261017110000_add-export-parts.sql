@@ -0,0 +1,13 @@
+ALTER TABLE Export ADD COLUMN split_mode TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE Export ADD COLUMN split_value INTEGER;
+
+CREATE TABLE ExportParts(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL,
+    part INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    series_index INTEGER,
+    UNIQUE (export_id, part),
+    FOREIGN KEY (export_id) REFERENCES Export(id)
+);
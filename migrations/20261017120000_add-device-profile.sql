@@ -0,0 +1,3 @@
+ALTER TABLE Export ADD COLUMN device_profile TEXT NOT NULL DEFAULT 'original';
+ALTER TABLE Export ADD COLUMN device_width INTEGER;
+ALTER TABLE Export ADD COLUMN device_height INTEGER;
@@ -0,0 +1,3 @@
+ALTER TABLE Export ADD COLUMN grayscale BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE Export ADD COLUMN gamma REAL;
+ALTER TABLE Export ADD COLUMN dither BOOLEAN NOT NULL DEFAULT FALSE;
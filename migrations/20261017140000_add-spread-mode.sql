@@ -0,0 +1 @@
+ALTER TABLE Export ADD COLUMN spread_mode TEXT NOT NULL DEFAULT 'keep';
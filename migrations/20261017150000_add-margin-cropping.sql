@@ -0,0 +1,5 @@
+ALTER TABLE Export ADD COLUMN crop_margins BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE Export ADD COLUMN crop_safety_margin INTEGER;
+ALTER TABLE Export ADD COLUMN crop_max_percent INTEGER;
+ALTER TABLE Export ADD COLUMN crop_pixels_total INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE Export ADD COLUMN crop_pixels_removed INTEGER NOT NULL DEFAULT 0;
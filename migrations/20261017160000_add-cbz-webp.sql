@@ -0,0 +1 @@
+ALTER TABLE Export ADD COLUMN cbz_webp BOOLEAN NOT NULL DEFAULT FALSE;
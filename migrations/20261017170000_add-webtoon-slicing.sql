@@ -0,0 +1 @@
+ALTER TABLE Export ADD COLUMN webtoon BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+CREATE TABLE ChapterPages(
+    id INTEGER PRIMARY KEY,
+    chapter_id INTEGER NOT NULL,
+    page_index INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    content_type TEXT,
+    byte_size INTEGER,
+    checksum TEXT,
+    filename TEXT,
+    status TEXT NOT NULL DEFAULT 'pending',
+    UNIQUE (chapter_id, page_index)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE ChapterPages ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE ChapterPages ADD COLUMN error TEXT;
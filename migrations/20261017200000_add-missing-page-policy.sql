@@ -0,0 +1,11 @@
+ALTER TABLE Export ADD COLUMN missing_page_policy TEXT NOT NULL DEFAULT 'fail';
+
+CREATE TABLE ExportMissingPages(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL,
+    chapter_id INTEGER NOT NULL,
+    page_index INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    error TEXT,
+    FOREIGN KEY (export_id) REFERENCES Export(id)
+);
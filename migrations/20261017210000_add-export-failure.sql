@@ -0,0 +1,2 @@
+ALTER TABLE Export ADD COLUMN error TEXT;
+ALTER TABLE Export ADD COLUMN failed_step TEXT;
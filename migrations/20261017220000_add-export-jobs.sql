@@ -0,0 +1,9 @@
+ALTER TABLE Export ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE ExportJobs(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL UNIQUE,
+    queued_at TEXT NOT NULL,
+    started_at TEXT,
+    FOREIGN KEY (export_id) REFERENCES Export(id)
+);
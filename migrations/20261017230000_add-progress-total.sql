@@ -0,0 +1 @@
+ALTER TABLE Export ADD COLUMN progress_total INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+ALTER TABLE ExportLogs ADD COLUMN level TEXT NOT NULL DEFAULT 'info';
+
+CREATE INDEX ExportLogsByExport ON ExportLogs (export_id, id);
@@ -0,0 +1,77 @@
+-- SQLite can't change a foreign key in place, so the tables that belong to an export are
+-- rebuilt with ON DELETE CASCADE. Rows of exports that no longer exist are left behind.
+
+CREATE TABLE ExportChapters_new(
+    export_id INTEGER,
+    chapter_id INTEGER,
+    PRIMARY KEY (export_id, chapter_id),
+    FOREIGN KEY (export_id) REFERENCES Export(id) ON DELETE CASCADE
+);
+INSERT INTO ExportChapters_new (export_id, chapter_id)
+SELECT export_id, chapter_id FROM ExportChapters
+WHERE export_id IN (SELECT id FROM Export);
+DROP TABLE ExportChapters;
+ALTER TABLE ExportChapters_new RENAME TO ExportChapters;
+
+CREATE INDEX ExportChaptersByChapter ON ExportChapters (chapter_id);
+
+CREATE TABLE ExportLogs_new(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL,
+    step TEXT NOT NULL,
+    message TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    level TEXT NOT NULL DEFAULT 'info',
+    FOREIGN KEY (export_id) REFERENCES Export(id) ON DELETE CASCADE
+);
+INSERT INTO ExportLogs_new (id, export_id, step, message, timestamp, level)
+SELECT id, export_id, step, message, timestamp, level FROM ExportLogs
+WHERE export_id IN (SELECT id FROM Export);
+DROP TABLE ExportLogs;
+ALTER TABLE ExportLogs_new RENAME TO ExportLogs;
+
+CREATE INDEX ExportLogsByExport ON ExportLogs (export_id, id);
+
+CREATE TABLE ExportParts_new(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL,
+    part INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    series_index INTEGER,
+    UNIQUE (export_id, part),
+    FOREIGN KEY (export_id) REFERENCES Export(id) ON DELETE CASCADE
+);
+INSERT INTO ExportParts_new (id, export_id, part, title, filename, series_index)
+SELECT id, export_id, part, title, filename, series_index FROM ExportParts
+WHERE export_id IN (SELECT id FROM Export);
+DROP TABLE ExportParts;
+ALTER TABLE ExportParts_new RENAME TO ExportParts;
+
+CREATE TABLE ExportMissingPages_new(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL,
+    chapter_id INTEGER NOT NULL,
+    page_index INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    error TEXT,
+    FOREIGN KEY (export_id) REFERENCES Export(id) ON DELETE CASCADE
+);
+INSERT INTO ExportMissingPages_new (id, export_id, chapter_id, page_index, action, error)
+SELECT id, export_id, chapter_id, page_index, action, error FROM ExportMissingPages
+WHERE export_id IN (SELECT id FROM Export);
+DROP TABLE ExportMissingPages;
+ALTER TABLE ExportMissingPages_new RENAME TO ExportMissingPages;
+
+CREATE TABLE ExportJobs_new(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL UNIQUE,
+    queued_at TEXT NOT NULL,
+    started_at TEXT,
+    FOREIGN KEY (export_id) REFERENCES Export(id) ON DELETE CASCADE
+);
+INSERT INTO ExportJobs_new (id, export_id, queued_at, started_at)
+SELECT id, export_id, queued_at, started_at FROM ExportJobs
+WHERE export_id IN (SELECT id FROM Export);
+DROP TABLE ExportJobs;
+ALTER TABLE ExportJobs_new RENAME TO ExportJobs;
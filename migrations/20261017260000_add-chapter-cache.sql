@@ -0,0 +1,18 @@
+CREATE TABLE ChapterCache(
+    chapter_id INTEGER PRIMARY KEY,
+    manga_id INTEGER,
+    manga_title TEXT,
+    chapter_name TEXT,
+    byte_size INTEGER NOT NULL,
+    last_used TEXT NOT NULL
+);
+
+CREATE INDEX ChapterCacheByLastUsed ON ChapterCache (last_used);
+
+-- Chapters downloaded before the cache was tracked, their manga is filled in the next time
+-- they're fetched
+INSERT INTO ChapterCache (chapter_id, byte_size, last_used)
+SELECT chapter_id, COALESCE(SUM(byte_size), 0), strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+FROM ChapterPages
+WHERE status = 'downloaded'
+GROUP BY chapter_id;
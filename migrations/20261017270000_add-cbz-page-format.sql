@@ -0,0 +1,3 @@
+ALTER TABLE Export ADD COLUMN cbz_page_format TEXT NOT NULL DEFAULT 'original';
+UPDATE Export SET cbz_page_format = 'webp' WHERE cbz_webp;
+ALTER TABLE Export DROP COLUMN cbz_webp;
@@ -0,0 +1,14 @@
+-- Chapters whose page list couldn't be fetched are recorded with no page index
+CREATE TABLE ExportMissingPages_new(
+    id INTEGER PRIMARY KEY,
+    export_id INTEGER NOT NULL,
+    chapter_id INTEGER NOT NULL,
+    page_index INTEGER,
+    action TEXT NOT NULL,
+    error TEXT,
+    FOREIGN KEY (export_id) REFERENCES Export(id) ON DELETE CASCADE
+);
+INSERT INTO ExportMissingPages_new (id, export_id, chapter_id, page_index, action, error)
+SELECT id, export_id, chapter_id, page_index, action, error FROM ExportMissingPages;
+DROP TABLE ExportMissingPages;
+ALTER TABLE ExportMissingPages_new RENAME TO ExportMissingPages;
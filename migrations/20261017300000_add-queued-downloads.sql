@@ -0,0 +1,3 @@
+-- Chapters the export put on Suwayomi's download queue itself, the only ones it may take off
+-- again when it's paused or cancelled
+ALTER TABLE ExportChapters ADD COLUMN download_queued BOOLEAN NOT NULL DEFAULT FALSE;
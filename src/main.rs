@@ -8,7 +8,7 @@ use axum::{
 };
 use dotenv::dotenv;
 use models::export::get_export_base_dir;
-use services::exporter::resume_interrupted_exports;
+use services::exporter::start_export_workers;
 use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 use std::{env, fs, path::Path};
 use std::{fmt::Debug, str::FromStr, sync::Arc};
@@ -92,12 +92,13 @@ async fn main() {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL in .env is required");
     dbg!(&db_url);
 
-    let connection_settings =
-        SqliteConnectOptions::from_str(&db_url)
-            .expect("Invalid DATABASE_URL")
-            .create_if_missing(true);
+    let connection_settings = SqliteConnectOptions::from_str(&db_url)
+        .expect("Invalid DATABASE_URL")
+        .create_if_missing(true);
 
-    let db_base_path = Path::new(connection_settings.get_filename()).parent().unwrap();
+    let db_base_path = Path::new(connection_settings.get_filename())
+        .parent()
+        .unwrap();
     fs::create_dir_all(db_base_path).unwrap();
     let pool = SqlitePool::connect_with(connection_settings)
         .await
@@ -113,9 +114,7 @@ async fn main() {
     let pool_clone = Arc::new(pool);
 
     // resume_interrupted_tasks(pool_clone.clone()).await.unwrap();
-    resume_interrupted_exports(pool_clone.clone())
-        .await
-        .unwrap();
+    start_export_workers(pool_clone.clone()).await.unwrap();
 
     let session_store = MemoryStore::default();
     let session_layer = SessionManagerLayer::new(session_store)
@@ -127,6 +126,7 @@ async fn main() {
         .nest("/export/new", views::export_new::get_routes())
         .nest("/export", views::export::get_routes())
         .nest("/exports", views::exports::get_routes())
+        .nest("/cache", views::cache::get_routes())
         .nest_service("/public", ServeDir::new("public"))
         .nest_service("/download", ServeDir::new(get_export_base_dir()))
         .fallback(not_found)
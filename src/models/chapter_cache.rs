@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+
+use crate::AppError;
+
+/// What's known about a cached chapter besides its pages
+pub struct CachedChapterInfo {
+    pub manga_id: i64,
+    pub manga_title: String,
+    pub chapter_name: String,
+}
+
+/// Cache usage of one manga. Chapters downloaded before the cache was tracked have no
+/// manga until they're fetched again.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MangaCacheUsage {
+    pub manga_id: Option<i64>,
+    pub manga_title: Option<String>,
+    pub chapters: i64,
+    pub byte_size: i64,
+    pub last_used: OffsetDateTime,
+}
+
+/// Eviction orders chapters by `last_used` as text, which only matches time order when every
+/// value is UTC in the same format. This is the format SQLite's `%f` produces.
+fn last_used_now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// Records a fetched chapter, its size is what the manifest says was downloaded
+pub async fn record_cached_chapter(
+    pool: &SqlitePool,
+    chapter_id: i64,
+    info: &CachedChapterInfo,
+) -> Result<(), AppError> {
+    let now = last_used_now();
+    sqlx::query!(
+        r#"
+        INSERT INTO ChapterCache (chapter_id, manga_id, manga_title, chapter_name, byte_size, last_used)
+        VALUES (?, ?, ?, ?, (
+            SELECT COALESCE(SUM(byte_size), 0)
+            FROM ChapterPages
+            WHERE chapter_id = ? AND status = 'downloaded'
+        ), ?)
+        ON CONFLICT (chapter_id) DO UPDATE
+        SET manga_id = excluded.manga_id, manga_title = excluded.manga_title,
+            chapter_name = excluded.chapter_name, byte_size = excluded.byte_size,
+            last_used = excluded.last_used
+        "#,
+        chapter_id,
+        info.manga_id,
+        info.manga_title,
+        info.chapter_name,
+        chapter_id,
+        now
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks the chapter as recently used, so it's among the last to be evicted
+pub async fn touch_cached_chapter(pool: &SqlitePool, chapter_id: i64) -> Result<(), AppError> {
+    let now = last_used_now();
+    sqlx::query!(
+        r#"
+        UPDATE ChapterCache
+        SET last_used = ?
+        WHERE chapter_id = ?
+        "#,
+        now,
+        chapter_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_cached_chapter(pool: &SqlitePool, chapter_id: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM ChapterCache
+        WHERE chapter_id = ?
+        "#,
+        chapter_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_chapter_cache_size(pool: &SqlitePool) -> Result<i64, AppError> {
+    let size = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(byte_size), 0) as "size!: i64"
+        FROM ChapterCache
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(size)
+}
+
+/// Cached chapters that can be evicted, least recently used first. Chapters of exports that
+/// are queued, running or paused are left alone, those exports still need them. So are the
+/// chapters of failed exports, a retry can resume after the download step.
+pub async fn get_evictable_chapters(pool: &SqlitePool) -> Result<Vec<(i64, i64)>, AppError> {
+    let chapters = sqlx::query!(
+        r#"
+        SELECT chapter_id as "chapter_id!", byte_size
+        FROM ChapterCache
+        WHERE chapter_id NOT IN (
+            SELECT ExportChapters.chapter_id
+            FROM ExportChapters
+            JOIN Export ON Export.id = ExportChapters.export_id
+            WHERE ExportChapters.chapter_id IS NOT NULL
+                AND (Export.state IN ('in_progress', 'paused', 'failed')
+                    OR Export.id IN (SELECT export_id FROM ExportJobs))
+        )
+        ORDER BY last_used ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(chapters
+        .into_iter()
+        .map(|chapter| (chapter.chapter_id, chapter.byte_size))
+        .collect())
+}
+
+/// The evictable chapters of one manga, `None` being the chapters whose manga isn't known
+pub async fn get_evictable_manga_chapters(
+    pool: &SqlitePool,
+    manga_id: Option<i64>,
+) -> Result<Vec<i64>, AppError> {
+    let evictable: HashSet<i64> = get_evictable_chapters(pool)
+        .await?
+        .into_iter()
+        .map(|(chapter_id, _)| chapter_id)
+        .collect();
+    let chapters = sqlx::query_scalar!(
+        r#"
+        SELECT chapter_id as "chapter_id!"
+        FROM ChapterCache
+        WHERE manga_id IS ?
+        "#,
+        manga_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(chapters
+        .into_iter()
+        .filter(|id| evictable.contains(id))
+        .collect())
+}
+
+pub async fn get_manga_cache_usage(pool: &SqlitePool) -> Result<Vec<MangaCacheUsage>, AppError> {
+    let usage = sqlx::query_as!(
+        MangaCacheUsage,
+        r#"
+        SELECT
+            manga_id,
+            MAX(manga_title) as manga_title,
+            COUNT(*) as "chapters!: i64",
+            COALESCE(SUM(byte_size), 0) as "byte_size!: i64",
+            MAX(last_used) as "last_used!: OffsetDateTime"
+        FROM ChapterCache
+        GROUP BY manga_id
+        ORDER BY SUM(byte_size) DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(usage)
+}
@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::AppError;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum PageStatus {
+    Pending,
+    Downloaded,
+    Failed,
+}
+
+/// One page of a downloaded chapter, in the order Suwayomi lists them. This is the source of
+/// truth for what's in the chapter cache, the files themselves are never counted.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, Clone)]
+pub struct ChapterPage {
+    pub id: i64,
+    pub chapter_id: i64,
+    pub page_index: i64,
+    pub url: String,
+    pub content_type: Option<String>,
+    pub byte_size: Option<i64>,
+    pub checksum: Option<String>,
+    pub filename: Option<String>,
+    pub status: PageStatus,
+    pub attempts: i64,
+    pub error: Option<String>,
+}
+
+impl ChapterPage {
+    pub fn get_path(&self, chapter_dir: &Path) -> Option<PathBuf> {
+        self.filename
+            .as_ref()
+            .map(|filename| chapter_dir.join(filename))
+    }
+}
+
+/// What a successful download leaves behind, `attempts` includes the successful one
+pub struct DownloadedPage {
+    pub content_type: String,
+    pub byte_size: i64,
+    pub checksum: String,
+    pub filename: String,
+    pub attempts: i64,
+}
+
+/// Brings the manifest in line with the page URLs Suwayomi returned. Pages whose URL changed
+/// go back to pending, pages past the end are dropped.
+pub async fn sync_chapter_manifest(
+    pool: &SqlitePool,
+    chapter_id: i64,
+    urls: &[String],
+) -> Result<Vec<ChapterPage>, AppError> {
+    let mut tx = pool.begin().await?;
+    for (index, url) in urls.iter().enumerate() {
+        let page_index = index as i64;
+        sqlx::query!(
+            r#"
+            INSERT INTO ChapterPages (chapter_id, page_index, url, status)
+            VALUES (?, ?, ?, 'pending')
+            ON CONFLICT (chapter_id, page_index) DO UPDATE
+            SET url = excluded.url, status = 'pending'
+            WHERE url != excluded.url
+            "#,
+            chapter_id,
+            page_index,
+            url
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    let page_count = urls.len() as i64;
+    sqlx::query!(
+        r#"
+        DELETE FROM ChapterPages
+        WHERE chapter_id = ? AND page_index >= ?
+        "#,
+        chapter_id,
+        page_count
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    get_chapter_manifest(pool, chapter_id).await
+}
+
+pub async fn get_chapter_manifest(
+    pool: &SqlitePool,
+    chapter_id: i64,
+) -> Result<Vec<ChapterPage>, AppError> {
+    let pages = sqlx::query_as!(
+        ChapterPage,
+        r#"
+        SELECT
+            id as "id!",
+            chapter_id,
+            page_index,
+            url,
+            content_type,
+            byte_size,
+            checksum,
+            filename,
+            status as "status: PageStatus",
+            attempts,
+            error
+        FROM ChapterPages
+        WHERE chapter_id = ?
+        ORDER BY page_index ASC
+        "#,
+        chapter_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(pages)
+}
+
+pub async fn set_page_downloaded(
+    pool: &SqlitePool,
+    id: i64,
+    page: &DownloadedPage,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE ChapterPages
+        SET content_type = ?, byte_size = ?, checksum = ?, filename = ?, status = 'downloaded',
+            attempts = ?, error = NULL
+        WHERE id = ?
+        "#,
+        page.content_type,
+        page.byte_size,
+        page.checksum,
+        page.filename,
+        page.attempts,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Keeps the last error so it can be shown once the retries have run out
+pub async fn set_page_failed(
+    pool: &SqlitePool,
+    id: i64,
+    attempts: i64,
+    error: &str,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE ChapterPages
+        SET status = 'failed', attempts = ?, error = ?
+        WHERE id = ?
+        "#,
+        attempts,
+        error,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Forgets the chapter's pages once its directory is gone from the chapter cache
+pub async fn delete_chapter_manifest(pool: &SqlitePool, chapter_id: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM ChapterPages
+        WHERE chapter_id = ?
+        "#,
+        chapter_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
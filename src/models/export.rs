@@ -16,6 +16,7 @@ pub enum ExportStep {
     Begin,
     DownloadingFromSource,
     FetchingFromSuwayomi,
+    ProcessingImages,
     AssemblingFile,
     Complete,
 }
@@ -26,19 +27,22 @@ impl std::fmt::Display for ExportStep {
             ExportStep::Begin => write!(f, "Draft"),
             ExportStep::DownloadingFromSource => write!(f, "Downloading from source"),
             ExportStep::FetchingFromSuwayomi => write!(f, "Fetching from Suwayomi"),
+            ExportStep::ProcessingImages => write!(f, "Processing images"),
             ExportStep::AssemblingFile => write!(f, "Assembling file"),
             ExportStep::Complete => write!(f, "Complete"),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
 #[sqlx(rename_all = "snake_case")]
 pub enum ExportState {
     Draft,
     InProgress,
     Completed,
     Failed,
+    Paused,
+    Cancelled,
 }
 
 impl std::fmt::Display for ExportState {
@@ -48,15 +52,20 @@ impl std::fmt::Display for ExportState {
             ExportState::InProgress => write!(f, "In progress"),
             ExportState::Completed => write!(f, "Completed"),
             ExportState::Failed => write!(f, "Failed"),
+            ExportState::Paused => write!(f, "Paused"),
+            ExportState::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
 #[sqlx(rename_all = "snake_case")]
 pub enum ExportFormat {
     Epub,
     Cbz,
+    Pdf,
+    Kepub,
+    Azw3,
 }
 
 impl std::fmt::Display for ExportFormat {
@@ -64,15 +73,180 @@ impl std::fmt::Display for ExportFormat {
         match self {
             ExportFormat::Epub => write!(f, "EPUB"),
             ExportFormat::Cbz => write!(f, "CBZ"),
+            ExportFormat::Pdf => write!(f, "PDF"),
+            ExportFormat::Kepub => write!(f, "KEPUB"),
+            ExportFormat::Azw3 => write!(f, "AZW3"),
         }
     }
 }
 
 impl ExportFormat {
-    pub fn to_extension(&self) -> &str {
+    pub fn to_extension(self) -> &'static str {
         match self {
             ExportFormat::Epub => "epub",
             ExportFormat::Cbz => "cbz",
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Kepub => "kepub.epub",
+            ExportFormat::Azw3 => "azw3",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq)]
+#[sqlx(rename_all = "snake_case")]
+pub enum EpubLayout {
+    Reflowable,
+    Fixed,
+}
+
+impl std::fmt::Display for EpubLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EpubLayout::Reflowable => write!(f, "Reflowable"),
+            EpubLayout::Fixed => write!(f, "Fixed layout"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum ReadingDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl std::fmt::Display for ReadingDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadingDirection::LeftToRight => write!(f, "Left to right"),
+            ReadingDirection::RightToLeft => write!(f, "Right to left"),
+        }
+    }
+}
+
+impl ReadingDirection {
+    /// Japanese manga reads right to left, while manhwa, manhua and webtoons (and
+    /// scanlations from most other sources) read left to right
+    pub fn default_for_manga(source_lang: Option<&str>, genres: &[String]) -> Self {
+        let has_genre = |name: &str| genres.iter().any(|genre| genre.eq_ignore_ascii_case(name));
+        if has_genre("manhwa") || has_genre("manhua") || has_genre("webtoon") {
+            return ReadingDirection::LeftToRight;
+        }
+        if source_lang == Some("ja") || has_genre("manga") {
+            return ReadingDirection::RightToLeft;
+        }
+        ReadingDirection::LeftToRight
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum CoverSource {
+    Thumbnail,
+    FirstPage,
+    Page,
+    Upload,
+}
+
+impl std::fmt::Display for CoverSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoverSource::Thumbnail => write!(f, "Series thumbnail"),
+            CoverSource::FirstPage => write!(f, "First page"),
+            CoverSource::Page => write!(f, "Specific page"),
+            CoverSource::Upload => write!(f, "Uploaded image"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum SplitMode {
+    None,
+    ChapterCount,
+    FileSize,
+    Volume,
+}
+
+impl std::fmt::Display for SplitMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitMode::None => write!(f, "Single file"),
+            SplitMode::ChapterCount => write!(f, "Every N chapters"),
+            SplitMode::FileSize => write!(f, "By file size (MB)"),
+            SplitMode::Volume => write!(f, "By volume"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum DeviceProfile {
+    Original,
+    KindlePaperwhite,
+    KoboLibra,
+    Boox,
+    Custom,
+}
+
+impl std::fmt::Display for DeviceProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceProfile::Original => write!(f, "Original size"),
+            DeviceProfile::KindlePaperwhite => write!(f, "Kindle Paperwhite"),
+            DeviceProfile::KoboLibra => write!(f, "Kobo Libra"),
+            DeviceProfile::Boox => write!(f, "Boox Note Air"),
+            DeviceProfile::Custom => write!(f, "Custom"),
+        }
+    }
+}
+
+/// What to do with double-page spreads, which show up tiny on portrait screens
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum SpreadMode {
+    Keep,
+    Split,
+    Rotate,
+    SplitAndKeep,
+}
+
+impl std::fmt::Display for SpreadMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpreadMode::Keep => write!(f, "Keep as is"),
+            SpreadMode::Split => write!(f, "Split into two pages"),
+            SpreadMode::Rotate => write!(f, "Rotate"),
+            SpreadMode::SplitAndKeep => write!(f, "Keep and add split pages"),
+        }
+    }
+}
+
+/// How CBZ pages are stored. Comic readers open more formats than e-readers, so pages can
+/// go to something smaller than PNG and JPEG.
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum CbzPageFormat {
+    Original,
+    Webp,
+    Avif,
+}
+
+/// What an export does about pages that couldn't be downloaded
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum MissingPagePolicy {
+    Fail,
+    SkipChapter,
+    Placeholder,
+}
+
+impl std::fmt::Display for MissingPagePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MissingPagePolicy::Fail => write!(f, "Failed the export"),
+            MissingPagePolicy::SkipChapter => write!(f, "Skipped the chapter"),
+            MissingPagePolicy::Placeholder => write!(f, "Inserted a placeholder"),
         }
     }
 }
@@ -83,16 +257,75 @@ pub struct Export {
     pub title: String,
     pub author: String,
     pub format: ExportFormat,
+    pub epub_layout: EpubLayout,
+    pub reading_direction: ReadingDirection,
+    pub cover_source: CoverSource,
+    pub cover_chapter_id: Option<i64>,
+    pub cover_page: Option<i64>,
+    pub cover_path: Option<String>,
+    pub split_mode: SplitMode,
+    pub split_value: Option<i64>,
+    pub device_profile: DeviceProfile,
+    pub device_width: Option<i64>,
+    pub device_height: Option<i64>,
+    pub grayscale: bool,
+    pub gamma: Option<f64>,
+    pub dither: bool,
+    pub spread_mode: SpreadMode,
+    pub crop_margins: bool,
+    pub crop_safety_margin: Option<i64>,
+    pub crop_max_percent: Option<i64>,
+    pub crop_pixels_total: i64,
+    pub crop_pixels_removed: i64,
+    pub cbz_page_format: CbzPageFormat,
+    pub webtoon: bool,
+    pub missing_page_policy: MissingPagePolicy,
+    pub priority: i64,
     pub state: ExportState,
     pub step: ExportStep,
     pub progress: i64,
+    pub progress_total: i64,
     pub created_at: OffsetDateTime,
+    pub error: Option<String>,
+    pub failed_step: Option<ExportStep>,
 }
 
 pub fn get_export_base_dir() -> String {
     env::var("EXPORT_PATH").unwrap_or("data/exports".to_string())
 }
 
+pub fn get_cover_base_dir() -> String {
+    env::var("COVER_PATH").unwrap_or("data/covers".to_string())
+}
+
+/// The user editable settings from the configure page
+pub struct ExportConfig {
+    pub title: String,
+    pub author: String,
+    pub format: ExportFormat,
+    pub epub_layout: EpubLayout,
+    pub reading_direction: ReadingDirection,
+    pub cover_source: CoverSource,
+    pub cover_chapter_id: Option<i64>,
+    pub cover_page: Option<i64>,
+    pub split_mode: SplitMode,
+    pub split_value: Option<i64>,
+    pub device_profile: DeviceProfile,
+    pub device_width: Option<i64>,
+    pub device_height: Option<i64>,
+    pub grayscale: bool,
+    pub gamma: Option<f64>,
+    pub dither: bool,
+    pub spread_mode: SpreadMode,
+    pub crop_margins: bool,
+    pub crop_safety_margin: Option<i64>,
+    pub crop_max_percent: Option<i64>,
+    pub cbz_page_format: CbzPageFormat,
+    pub webtoon: bool,
+    pub missing_page_policy: MissingPagePolicy,
+    pub priority: i64,
+}
+
 impl Export {
     pub fn get_filename(&self) -> String {
         let extension = self.format.to_extension();
@@ -103,6 +336,28 @@ impl Export {
         let filename = self.get_filename();
         Path::new(&base_dir).join(filename)
     }
+    /// Share of the page area margin cropping removed, None if nothing was cropped
+    pub fn get_crop_saved_percent(&self) -> Option<f64> {
+        if self.crop_pixels_total == 0 || self.crop_pixels_removed == 0 {
+            return None;
+        }
+        Some(self.crop_pixels_removed as f64 * 100.0 / self.crop_pixels_total as f64)
+    }
+    /// The screen size pages get scaled down to, None keeps them as downloaded
+    pub fn get_target_resolution(&self) -> Option<(u32, u32)> {
+        match self.device_profile {
+            DeviceProfile::Original => None,
+            DeviceProfile::KindlePaperwhite => Some((1236, 1648)),
+            DeviceProfile::KoboLibra => Some((1264, 1680)),
+            DeviceProfile::Boox => Some((1404, 1872)),
+            DeviceProfile::Custom => match (self.device_width, self.device_height) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => {
+                    Some((width as u32, height as u32))
+                }
+                _ => None,
+            },
+        }
+    }
 }
 
 pub async fn get_export_by_id(pool: &SqlitePool, id: i64) -> Result<Option<Export>, AppError> {
@@ -114,10 +369,37 @@ pub async fn get_export_by_id(pool: &SqlitePool, id: i64) -> Result<Option<Expor
             title,
             author,
             format as "format: ExportFormat",
+            epub_layout as "epub_layout: EpubLayout",
+            reading_direction as "reading_direction: ReadingDirection",
+            cover_source as "cover_source: CoverSource",
+            cover_chapter_id,
+            cover_page,
+            cover_path,
+            split_mode as "split_mode: SplitMode",
+            split_value,
+            device_profile as "device_profile: DeviceProfile",
+            device_width,
+            device_height,
+            grayscale,
+            gamma,
+            dither,
+            spread_mode as "spread_mode: SpreadMode",
+            crop_margins,
+            crop_safety_margin,
+            crop_max_percent,
+            crop_pixels_total,
+            crop_pixels_removed,
+            cbz_page_format as "cbz_page_format: CbzPageFormat",
+            webtoon,
+            missing_page_policy as "missing_page_policy: MissingPagePolicy",
+            priority,
             state as "state: ExportState",
             step as "step: ExportStep",
             progress,
-            created_at as "created_at: OffsetDateTime"
+            progress_total,
+            created_at as "created_at: OffsetDateTime",
+            error,
+            failed_step as "failed_step: ExportStep"
         FROM Export WHERE Export.id = ?"#,
         id
     )
@@ -130,17 +412,19 @@ pub async fn insert_export(
     pool: &SqlitePool,
     title: &str,
     author: &str,
+    reading_direction: ReadingDirection,
 ) -> Result<i64, sqlx::Error> {
     let now = chrono::Local::now().to_rfc3339();
     let id = sqlx::query!(
         r#"
-        INSERT INTO Export (title, author, format, state, step, progress, created_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?)
+        INSERT INTO Export (title, author, format, reading_direction, state, step, progress, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
         RETURNING id
         "#,
         title,
         author,
         ExportFormat::Cbz,
+        reading_direction,
         ExportState::Draft,
         ExportStep::Begin,
         0,
@@ -157,13 +441,14 @@ pub async fn create_export(
     pool: &SqlitePool,
     title: &str,
     author: &str,
+    reading_direction: ReadingDirection,
 ) -> Result<i64, sqlx::Error> {
-    let id = match insert_export(pool, title, author).await {
+    let id = match insert_export(pool, title, author, reading_direction).await {
         Ok(id) => Ok(id),
         Err(sqlx::Error::Database(e)) => match e.kind() {
             ErrorKind::UniqueViolation => {
                 let new_title = format!("{} ({})", title, OffsetDateTime::now_utc());
-                Ok(insert_export(pool, &new_title, author).await?)
+                Ok(insert_export(pool, &new_title, author, reading_direction).await?)
             }
             _ => Err(sqlx::Error::Database(e)),
         },
@@ -209,19 +494,63 @@ pub async fn set_chapters_for_export(
 pub async fn set_export_config(
     pool: &SqlitePool,
     id: i64,
-    title: &str,
-    author: &str,
-    format: ExportFormat,
+    config: &ExportConfig,
 ) -> Result<(), AppError> {
     sqlx::query!(
         r#"
         UPDATE Export
-        SET title = ?, author = ?, format = ?
+        SET title = ?, author = ?, format = ?, epub_layout = ?, reading_direction = ?,
+            cover_source = ?, cover_chapter_id = ?, cover_page = ?, split_mode = ?, split_value = ?,
+            device_profile = ?, device_width = ?, device_height = ?,
+            grayscale = ?, gamma = ?, dither = ?, spread_mode = ?,
+            crop_margins = ?, crop_safety_margin = ?, crop_max_percent = ?, cbz_page_format = ?,
+            webtoon = ?, missing_page_policy = ?, priority = ?
         WHERE id = ?
         "#,
-        title,
-        author,
-        format,
+        config.title,
+        config.author,
+        config.format,
+        config.epub_layout,
+        config.reading_direction,
+        config.cover_source,
+        config.cover_chapter_id,
+        config.cover_page,
+        config.split_mode,
+        config.split_value,
+        config.device_profile,
+        config.device_width,
+        config.device_height,
+        config.grayscale,
+        config.gamma,
+        config.dither,
+        config.spread_mode,
+        config.crop_margins,
+        config.crop_safety_margin,
+        config.crop_max_percent,
+        config.cbz_page_format,
+        config.webtoon,
+        config.missing_page_policy,
+        config.priority,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_export_cover_upload(
+    pool: &SqlitePool,
+    id: i64,
+    cover_path: &str,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE Export
+        SET cover_source = ?, cover_path = ?
+        WHERE id = ?
+        "#,
+        CoverSource::Upload,
+        cover_path,
         id
     )
     .execute(pool)
@@ -250,18 +579,83 @@ pub async fn set_export_state(
     Ok(())
 }
 
+/// Keeps the step that failed so a retry can pick up from there
+pub async fn set_export_failed(
+    pool: &SqlitePool,
+    id: i64,
+    step: ExportStep,
+    error: &str,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE Export
+        SET state = ?, step = ?, failed_step = ?, error = ?
+        WHERE id = ?
+        "#,
+        ExportState::Failed,
+        step,
+        step,
+        error,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Puts a failed export back in progress at the step that failed. Returns false when the
+/// export wasn't failed.
+pub async fn set_export_retrying(pool: &SqlitePool, id: i64) -> Result<bool, AppError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE Export
+        SET state = ?, step = COALESCE(failed_step, step), failed_step = NULL, error = NULL
+        WHERE id = ? AND state = ?
+        "#,
+        ExportState::InProgress,
+        id,
+        ExportState::Failed
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_export_crop_stats(
+    pool: &SqlitePool,
+    id: i64,
+    pixels_total: i64,
+    pixels_removed: i64,
+) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE Export
+        SET crop_pixels_total = ?, crop_pixels_removed = ?
+        WHERE id = ?
+        "#,
+        pixels_total,
+        pixels_removed,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 pub async fn set_export_progress(
     pool: &SqlitePool,
     id: i64,
     progress: i64,
+    progress_total: i64,
 ) -> Result<(), AppError> {
     sqlx::query!(
         r#"
         UPDATE Export
-        SET progress = ?
+        SET progress = ?, progress_total = ?
         WHERE id = ?
         "#,
         progress,
+        progress_total,
         id
     )
     .execute(pool)
@@ -284,12 +678,120 @@ pub async fn get_export_chapters_by_id(
     .fetch_all(pool)
     .await?
     .into_iter()
-    .map(|row| row.chapter_id)
-    .filter_map(|chapter_id| chapter_id)
+    .filter_map(|row| row.chapter_id)
+    .collect();
+    Ok(chapters)
+}
+
+/// Remembers which of the export's chapters it queued for download on Suwayomi
+pub async fn set_chapter_downloads_queued(
+    pool: &SqlitePool,
+    id: i64,
+    chapter_ids: &[i64],
+) -> Result<(), AppError> {
+    for chapter_id in chapter_ids {
+        sqlx::query!(
+            r#"
+            UPDATE ExportChapters
+            SET download_queued = TRUE
+            WHERE export_id = ? AND chapter_id = ?
+            "#,
+            id,
+            chapter_id
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn clear_chapter_downloads_queued(pool: &SqlitePool, id: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE ExportChapters
+        SET download_queued = FALSE
+        WHERE export_id = ?
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Chapters the export queued for download that no other queued or running export includes,
+/// so taking them off Suwayomi's queue can't hold up anyone else
+pub async fn get_dequeueable_chapter_downloads(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<HashSet<i64>, AppError> {
+    let chapters: HashSet<i64> = sqlx::query!(
+        r#"
+        SELECT chapter_id
+        FROM ExportChapters
+        WHERE export_id = ? AND download_queued
+        AND chapter_id NOT IN (
+            SELECT ExportChapters.chapter_id
+            FROM ExportChapters
+            JOIN Export ON Export.id = ExportChapters.export_id
+            WHERE ExportChapters.export_id != ?
+            AND (
+                Export.state = ?
+                OR Export.id IN (SELECT export_id FROM ExportJobs)
+            )
+        )
+        "#,
+        id,
+        id,
+        ExportState::InProgress
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .filter_map(|row| row.chapter_id)
     .collect();
     Ok(chapters)
 }
 
+/// Everything that belongs to the export in the database goes with it, files are left to the
+/// caller. Returns false when there was no such export.
+pub async fn delete_export_by_id(pool: &SqlitePool, id: i64) -> Result<bool, AppError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM Export
+        WHERE id = ?
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Of the given chapters, the ones no export includes anymore
+pub async fn get_unreferenced_chapters(
+    pool: &SqlitePool,
+    chapter_ids: &HashSet<i64>,
+) -> Result<Vec<i64>, AppError> {
+    let mut unreferenced = Vec::new();
+    for chapter_id in chapter_ids {
+        let referenced = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM ExportChapters WHERE chapter_id = ?
+            ) as "referenced!: bool"
+            "#,
+            chapter_id
+        )
+        .fetch_one(pool)
+        .await?;
+        if !referenced {
+            unreferenced.push(*chapter_id);
+        }
+    }
+    Ok(unreferenced)
+}
+
 pub async fn get_export_and_chapters_by_id(
     pool: &SqlitePool,
     id: i64,
@@ -306,8 +808,7 @@ pub async fn get_export_and_chapters_by_id(
     .fetch_all(pool)
     .await?
     .into_iter()
-    .map(|row| row.chapter_id)
-    .filter_map(|chapter_id| chapter_id)
+    .filter_map(|row| row.chapter_id)
     .collect();
 
     Ok(export.map(|export| (export, chapters)))
@@ -322,10 +823,37 @@ pub async fn get_export_list(pool: &SqlitePool) -> Result<Vec<Export>, AppError>
             title,
             author,
             format as "format: ExportFormat",
+            epub_layout as "epub_layout: EpubLayout",
+            reading_direction as "reading_direction: ReadingDirection",
+            cover_source as "cover_source: CoverSource",
+            cover_chapter_id,
+            cover_page,
+            cover_path,
+            split_mode as "split_mode: SplitMode",
+            split_value,
+            device_profile as "device_profile: DeviceProfile",
+            device_width,
+            device_height,
+            grayscale,
+            gamma,
+            dither,
+            spread_mode as "spread_mode: SpreadMode",
+            crop_margins,
+            crop_safety_margin,
+            crop_max_percent,
+            crop_pixels_total,
+            crop_pixels_removed,
+            cbz_page_format as "cbz_page_format: CbzPageFormat",
+            webtoon,
+            missing_page_policy as "missing_page_policy: MissingPagePolicy",
+            priority,
             state as "state: ExportState",
             step as "step: ExportStep",
             progress,
-            created_at as "created_at: OffsetDateTime"
+            progress_total,
+            created_at as "created_at: OffsetDateTime",
+            error,
+            failed_step as "failed_step: ExportStep"
         FROM Export
         ORDER BY id ASC
         "#
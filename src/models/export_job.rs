@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use sqlx::SqlitePool;
+
+use crate::AppError;
+
+/// Adds the export to the queue. Exports already queued or running keep their place.
+pub async fn enqueue_export_job(pool: &SqlitePool, export_id: i64) -> Result<(), AppError> {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query!(
+        r#"
+        INSERT INTO ExportJobs (export_id, queued_at)
+        VALUES (?, ?)
+        ON CONFLICT (export_id) DO NOTHING
+        "#,
+        export_id,
+        now
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Takes the next job off the queue: highest priority first, then first come first served.
+/// Job ids only grow, so they give the queue order without comparing timestamps.
+pub async fn claim_next_export_job(pool: &SqlitePool) -> Result<Option<i64>, AppError> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let claimed = sqlx::query!(
+        r#"
+        UPDATE ExportJobs
+        SET started_at = ?
+        WHERE id = (
+            SELECT ExportJobs.id
+            FROM ExportJobs
+            JOIN Export ON Export.id = ExportJobs.export_id
+            WHERE ExportJobs.started_at IS NULL
+            ORDER BY Export.priority DESC, ExportJobs.id ASC
+            LIMIT 1
+        )
+        RETURNING export_id
+        "#,
+        now
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(claimed.map(|job| job.export_id))
+}
+
+pub async fn remove_export_job(pool: &SqlitePool, export_id: i64) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM ExportJobs
+        WHERE export_id = ?
+        "#,
+        export_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Jobs that were running when the server stopped go back to waiting
+pub async fn requeue_started_export_jobs(pool: &SqlitePool) -> Result<(), AppError> {
+    sqlx::query!(
+        r#"
+        UPDATE ExportJobs
+        SET started_at = NULL
+        WHERE started_at IS NOT NULL
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Position in the queue of every export that's waiting to start, counting from 1
+pub async fn get_export_queue_positions(pool: &SqlitePool) -> Result<HashMap<i64, i64>, AppError> {
+    let waiting = sqlx::query!(
+        r#"
+        SELECT ExportJobs.export_id
+        FROM ExportJobs
+        JOIN Export ON Export.id = ExportJobs.export_id
+        WHERE ExportJobs.started_at IS NULL
+        ORDER BY Export.priority DESC, ExportJobs.id ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(waiting
+        .into_iter()
+        .enumerate()
+        .map(|(index, job)| (job.export_id, index as i64 + 1))
+        .collect())
+}
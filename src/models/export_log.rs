@@ -1,45 +1,97 @@
-use chrono::{DateTime, Local};
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use time::{format_description, OffsetDateTime};
 
 use crate::AppError;
 
 use crate::models::export::ExportStep;
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+/// How many log lines are shown or returned at once
+pub const LOG_PAGE_SIZE: i64 = 50;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, PartialEq, Clone, Copy)]
+#[sqlx(rename_all = "snake_case")]
+pub enum LogLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogLevel::Info => write!(f, "Info"),
+            LogLevel::Warning => write!(f, "Warning"),
+            LogLevel::Error => write!(f, "Error"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct ExportLog {
-    id: i64,
-    export_id: i64,
-    step: ExportStep,
-    message: String,
-    timestamp: DateTime<Local>,
+    pub id: i64,
+    pub export_id: i64,
+    pub step: ExportStep,
+    pub level: LogLevel,
+    pub message: String,
+    #[serde(serialize_with = "time::serde::rfc3339::serialize")]
+    pub timestamp: OffsetDateTime,
+}
+
+impl ExportLog {
+    pub fn display_timestamp(&self) -> String {
+        format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")
+            .ok()
+            .and_then(|format| self.timestamp.format(&format).ok())
+            .unwrap_or_else(|| self.timestamp.to_string())
+    }
 }
 
-impl std::fmt::Display for ExportLog {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for ExportLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "[{}] export {}, step {}, message {}",
-            self.timestamp, self.export_id, self.step, self.message
+            "[{}] export {}, step {}, {}: {}",
+            self.timestamp, self.export_id, self.step, self.level, self.message
         )
     }
 }
 
+/// Narrows down an export's logs, filters left empty match everything
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub step: Option<ExportStep>,
+    pub level: Option<LogLevel>,
+}
+
 pub async fn log_export_step(
     pool: &SqlitePool,
     export_id: i64,
     step: ExportStep,
     message: &str,
+) -> Result<(), AppError> {
+    log_export(pool, export_id, step, LogLevel::Info, message).await
+}
+
+pub async fn log_export(
+    pool: &SqlitePool,
+    export_id: i64,
+    step: ExportStep,
+    level: LogLevel,
+    message: &str,
 ) -> Result<(), AppError> {
     let now = chrono::Local::now().to_rfc3339();
     println!(
-        "[{}] export {}, step {}, message {}",
-        now, export_id, step, message
+        "[{}] export {}, step {}, {}: {}",
+        now, export_id, step, level, message
     );
     sqlx::query!(
-        "INSERT INTO ExportLogs (export_id, step, message, timestamp) VALUES (?, ?, ?, ?)",
+        "INSERT INTO ExportLogs (export_id, step, level, message, timestamp) VALUES (?, ?, ?, ?, ?)",
         export_id,
         step,
+        level,
         message,
         now,
     )
@@ -48,3 +100,58 @@ pub async fn log_export_step(
 
     Ok(())
 }
+
+/// One page of the export's logs, newest first, along with how many logs match the filter.
+/// Pages are numbered from 1.
+pub async fn get_export_logs(
+    pool: &SqlitePool,
+    export_id: i64,
+    filter: &LogFilter,
+    page: i64,
+) -> Result<(Vec<ExportLog>, i64), AppError> {
+    let offset = (page.max(1) - 1) * LOG_PAGE_SIZE;
+    let logs = sqlx::query_as!(
+        ExportLog,
+        r#"
+        SELECT
+            id as "id!",
+            export_id,
+            step as "step: ExportStep",
+            level as "level: LogLevel",
+            message,
+            timestamp as "timestamp: OffsetDateTime"
+        FROM ExportLogs
+        WHERE export_id = ?
+            AND (? IS NULL OR step = ?)
+            AND (? IS NULL OR level = ?)
+        ORDER BY id DESC
+        LIMIT ? OFFSET ?
+        "#,
+        export_id,
+        filter.step,
+        filter.step,
+        filter.level,
+        filter.level,
+        LOG_PAGE_SIZE,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*)
+        FROM ExportLogs
+        WHERE export_id = ?
+            AND (? IS NULL OR step = ?)
+            AND (? IS NULL OR level = ?)
+        "#,
+        export_id,
+        filter.step,
+        filter.step,
+        filter.level,
+        filter.level
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((logs, total))
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::AppError;
+
+use crate::models::export::get_export_base_dir;
+
+/// One output file of an export. Exports that aren't split have a single part.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, Clone)]
+pub struct ExportPart {
+    pub id: i64,
+    pub export_id: i64,
+    pub part: i64,
+    pub title: String,
+    pub filename: String,
+    pub series_index: Option<i64>,
+}
+
+impl ExportPart {
+    pub fn get_path(&self) -> PathBuf {
+        Path::new(&get_export_base_dir()).join(&self.filename)
+    }
+
+    /// Assemblers write here and the file is only moved to [`Self::get_path`] once it's
+    /// complete, so an interrupted export never leaves a truncated file behind
+    pub fn get_temp_path(&self) -> PathBuf {
+        Path::new(&get_export_base_dir()).join(format!("{}.partial", self.filename))
+    }
+
+    /// Identifies the part inside the book metadata, stable across re-exports
+    pub fn get_identifier(&self) -> String {
+        match self.series_index {
+            Some(_) => format!(
+                "urn:manga-epub-browser:export:{}:part:{}",
+                self.export_id, self.part
+            ),
+            None => format!("urn:manga-epub-browser:export:{}", self.export_id),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NewExportPart {
+    pub title: String,
+    pub filename: String,
+    pub series_index: Option<i64>,
+}
+
+/// Replaces whatever parts a previous attempt left behind, parts are numbered from 1
+pub async fn set_export_parts(
+    pool: &SqlitePool,
+    export_id: i64,
+    parts: &[NewExportPart],
+) -> Result<Vec<ExportPart>, AppError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM ExportParts
+        WHERE export_id = ?
+        "#,
+        export_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for (index, part) in parts.iter().enumerate() {
+        let number = index as i64 + 1;
+        sqlx::query!(
+            r#"
+            INSERT INTO ExportParts (export_id, part, title, filename, series_index)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            export_id,
+            number,
+            part.title,
+            part.filename,
+            part.series_index
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    get_export_parts(pool, export_id).await
+}
+
+pub async fn get_export_parts(
+    pool: &SqlitePool,
+    export_id: i64,
+) -> Result<Vec<ExportPart>, AppError> {
+    let parts = sqlx::query_as!(
+        ExportPart,
+        r#"
+        SELECT id as "id!", export_id, part, title, filename, series_index
+        FROM ExportParts
+        WHERE export_id = ?
+        ORDER BY part ASC
+        "#,
+        export_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(parts)
+}
+
+pub async fn get_export_part(
+    pool: &SqlitePool,
+    export_id: i64,
+    part: i64,
+) -> Result<Option<ExportPart>, AppError> {
+    let part = sqlx::query_as!(
+        ExportPart,
+        r#"
+        SELECT id as "id!", export_id, part, title, filename, series_index
+        FROM ExportParts
+        WHERE export_id = ? AND part = ?
+        "#,
+        export_id,
+        part
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(part)
+}
@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::AppError;
+
+use crate::models::export::MissingPagePolicy;
+
+/// A page an export had to do without, and what was done about it. Chapters whose page
+/// list couldn't be fetched at all have no page index.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, Clone)]
+pub struct MissingPage {
+    pub id: i64,
+    pub export_id: i64,
+    pub chapter_id: i64,
+    pub page_index: Option<i64>,
+    pub action: MissingPagePolicy,
+    pub error: Option<String>,
+}
+
+impl MissingPage {
+    /// Pages are numbered from 1 wherever they're shown
+    pub fn page_number(&self) -> Option<i64> {
+        self.page_index.map(|index| index + 1)
+    }
+}
+
+pub struct NewMissingPage {
+    pub chapter_id: i64,
+    pub page_index: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// Replaces the list from a previous attempt, pages may have been fetched since
+pub async fn set_missing_pages(
+    pool: &SqlitePool,
+    export_id: i64,
+    action: MissingPagePolicy,
+    pages: &[NewMissingPage],
+) -> Result<(), AppError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM ExportMissingPages
+        WHERE export_id = ?
+        "#,
+        export_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for page in pages {
+        sqlx::query!(
+            r#"
+            INSERT INTO ExportMissingPages (export_id, chapter_id, page_index, action, error)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+            export_id,
+            page.chapter_id,
+            page.page_index,
+            action,
+            page.error
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_missing_pages(
+    pool: &SqlitePool,
+    export_id: i64,
+) -> Result<Vec<MissingPage>, AppError> {
+    let pages = sqlx::query_as!(
+        MissingPage,
+        r#"
+        SELECT
+            id as "id!",
+            export_id,
+            chapter_id,
+            page_index,
+            action as "action: MissingPagePolicy",
+            error
+        FROM ExportMissingPages
+        WHERE export_id = ?
+        ORDER BY chapter_id ASC, page_index ASC
+        "#,
+        export_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(pages)
+}
+
+/// Chapters left out of the export because of missing pages
+pub async fn get_skipped_chapters(
+    pool: &SqlitePool,
+    export_id: i64,
+) -> Result<HashSet<i64>, AppError> {
+    let skipped = get_missing_pages(pool, export_id)
+        .await?
+        .into_iter()
+        .filter(|page| page.action == MissingPagePolicy::SkipChapter)
+        .map(|page| page.chapter_id)
+        .collect();
+    Ok(skipped)
+}
+
+/// Chapters without a page list that get a placeholder in place of their pages
+pub async fn get_placeholder_chapters(
+    pool: &SqlitePool,
+    export_id: i64,
+) -> Result<HashSet<i64>, AppError> {
+    let chapters = get_missing_pages(pool, export_id)
+        .await?
+        .into_iter()
+        .filter(|page| page.page_index.is_none() && page.action == MissingPagePolicy::Placeholder)
+        .map(|page| page.chapter_id)
+        .collect();
+    Ok(chapters)
+}
@@ -1,2 +1,7 @@
+pub mod chapter_cache;
+pub mod chapter_page;
 pub mod export;
-pub mod export_log;
\ No newline at end of file
+pub mod export_job;
+pub mod export_log;
+pub mod export_part;
+pub mod missing_page;
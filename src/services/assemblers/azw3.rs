@@ -0,0 +1,1027 @@
+use std::{collections::HashSet, fs, io::Cursor, sync::Arc};
+
+use eyre::eyre;
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{
+        export::{get_export_base_dir, Export, ReadingDirection},
+        export_log::log_export_step,
+        export_part::ExportPart,
+    },
+    services::{control::check_interrupted, cover::get_cover_image, progress},
+    suwayomi::get_chapters_by_ids,
+    util::escape_xml,
+    AppError,
+};
+
+use super::{get_export_chapter_pages, get_manga_language};
+
+// KF8 (AZW3) is a PalmDB container holding a MOBI 8 header, the book markup split into
+// "skeletons" and "fragments", a handful of INDX indices describing that split and the table
+// of contents, and the images as raw resource records. The layout below follows what
+// kindlegen and calibre produce for KF8-only files.
+
+const TEXT_RECORD_SIZE: usize = 4096;
+const NULL_INDEX: u32 = 0xFFFF_FFFF;
+const INDX_HEADER_LENGTH: usize = 192;
+/// Index records are addressed with u16 offsets, leave room for the header and IDXT
+const INDX_RECORD_LIMIT: usize = 0x10000 - INDX_HEADER_LENGTH - 1048;
+const CNCX_RECORD_LIMIT: usize = 0xFBF8;
+const EOF_RECORD: &[u8] = b"\xe9\x8e\r\n";
+
+/// (tag, values per entry, bitmask, end flag), as written into the TAGX section
+type TagDefinition = (u8, u8, u8, u8);
+
+const SKELETON_TAGS: &[TagDefinition] = &[(1, 1, 3, 0), (6, 2, 12, 0), (0, 0, 0, 1)];
+const FRAGMENT_TAGS: &[TagDefinition] = &[
+    (2, 1, 1, 0),
+    (3, 1, 2, 0),
+    (4, 1, 4, 0),
+    (6, 2, 8, 0),
+    (0, 0, 0, 1),
+];
+const NCX_TAGS: &[TagDefinition] = &[
+    (1, 1, 1, 0),
+    (2, 1, 2, 0),
+    (3, 1, 4, 0),
+    (4, 1, 8, 0),
+    (6, 2, 128, 0),
+    (0, 0, 0, 1),
+];
+
+struct Skeleton {
+    start: usize,
+    length: usize,
+}
+
+struct Fragment {
+    insert_position: usize,
+    selector: String,
+    file_number: usize,
+    length: usize,
+}
+
+struct TocEntry {
+    title: String,
+    start: usize,
+    length: usize,
+    fragment_number: usize,
+}
+
+/// Accumulates the KF8 markup one page (XHTML file) at a time
+#[derive(Default)]
+struct Kf8Text {
+    text: Vec<u8>,
+    skeletons: Vec<Skeleton>,
+    fragments: Vec<Fragment>,
+}
+
+impl Kf8Text {
+    /// Each page becomes its own skeleton with a single fragment holding the image
+    fn add_page(
+        &mut self,
+        title: &str,
+        resource_number: usize,
+        mime_type: &str,
+        width: u32,
+        height: u32,
+    ) {
+        let file_number = self.skeletons.len();
+        let aid = to_base32(file_number, 1);
+        let head = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>{title}</title><meta name=\"viewport\" content=\"width={width}, height={height}\"/><style type=\"text/css\">body {{ margin: 0; padding: 0; }} img {{ display: block; width: {width}px; height: {height}px; }}</style></head><body aid=\"{aid}\">",
+            title = escape_xml(title),
+            width = width,
+            height = height,
+            aid = aid,
+        );
+        let tail = "</body></html>";
+        let fragment = format!(
+            "<div><img src=\"kindle:embed:{}?mime={}\" alt=\"\"/></div>",
+            to_base32(resource_number, 4),
+            mime_type
+        );
+
+        let start = self.text.len();
+        self.text.extend_from_slice(head.as_bytes());
+        self.text.extend_from_slice(tail.as_bytes());
+        self.text.extend_from_slice(fragment.as_bytes());
+        self.skeletons.push(Skeleton {
+            start,
+            length: head.len() + tail.len(),
+        });
+        self.fragments.push(Fragment {
+            insert_position: start + head.len(),
+            selector: format!("P-//*[@aid=\"{}\"]", aid),
+            file_number,
+            length: fragment.len(),
+        });
+    }
+
+    /// Splits the markup into PalmDOC sized records. Records that end part way through a
+    /// UTF-8 character carry the rest of it as a trailing "multibyte" entry.
+    fn to_records(&self) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        let mut start = 0;
+        while start < self.text.len() {
+            let end = (start + TEXT_RECORD_SIZE).min(self.text.len());
+            let mut record = self.text[start..end].to_vec();
+            let mut overlap_end = end;
+            while overlap_end < self.text.len() && (self.text[overlap_end] & 0xC0) == 0x80 {
+                overlap_end += 1;
+            }
+            record.extend_from_slice(&self.text[end..overlap_end]);
+            record.push((overlap_end - end) as u8);
+            records.push(record);
+            start = end;
+        }
+        records
+    }
+}
+
+pub async fn assemble_azw3(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    part: &ExportPart,
+    chapter_ids: &HashSet<i64>,
+) -> Result<(), AppError> {
+    let chapters = match get_chapters_by_ids(chapter_ids).await? {
+        Some(chapters) => chapters.nodes,
+        None => return Err(eyre!("Chapters not found").into()),
+    };
+    let language = match chapters.first() {
+        Some(chapter) => get_manga_language(chapter.manga_id).await?,
+        None => return Err(eyre!("Export has no chapters").into()),
+    };
+
+    let mut text = Kf8Text::default();
+    let mut resources: Vec<Vec<u8>> = Vec::new();
+    let mut toc: Vec<TocEntry> = Vec::new();
+    let mut cover_resource = None;
+    let mut resolution = None;
+
+    if let Some(cover) = get_cover_image(export, &chapters).await? {
+        let (width, height) = image::ImageReader::new(Cursor::new(&cover.data))
+            .with_guessed_format()?
+            .into_dimensions()?;
+        cover_resource = Some(resources.len());
+        text.add_page(
+            "Cover",
+            resources.len() + 1,
+            &cover.mime_type(),
+            width,
+            height,
+        );
+        resources.push(cover.data);
+    }
+
+    for chapter in chapters.iter() {
+        let chapter_start = text.text.len();
+        let first_fragment = text.fragments.len();
+        for page in get_export_chapter_pages(export.id, chapter.id)? {
+            check_interrupted(export.id)?;
+            progress::advance(export.id, 1);
+            let extension = page
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .ok_or(eyre!("Page {:?} has no extension", page))?;
+            let (width, height) = image::image_dimensions(&page)?;
+            resolution.get_or_insert((width, height));
+            text.add_page(
+                &chapter.name,
+                resources.len() + 1,
+                &format!("image/{}", extension),
+                width,
+                height,
+            );
+            resources.push(fs::read(&page)?);
+        }
+        if text.fragments.len() > first_fragment {
+            toc.push(TocEntry {
+                title: chapter.name.to_owned(),
+                start: chapter_start,
+                length: text.text.len() - chapter_start,
+                fragment_number: first_fragment,
+            });
+        }
+        log_export_step(
+            &pool,
+            export.id,
+            export.step,
+            format!("Added chapter {} to azw3", chapter.id).as_str(),
+        )
+        .await?;
+    }
+    if text.skeletons.is_empty() {
+        return Err(eyre!("Export has no pages").into());
+    }
+
+    // Record 0 is filled in once we know where everything else ended up
+    let mut records: Vec<Vec<u8>> = vec![Vec::new()];
+
+    let text_records = text.to_records();
+    let text_record_count = text_records.len();
+    let text_records_size: usize = text_records.iter().map(|r| r.len()).sum();
+    records.extend(text_records);
+    if !text_records_size.is_multiple_of(4) {
+        records.push(vec![0; 4 - text_records_size % 4]);
+    }
+    let first_non_text_record = records.len();
+
+    let fragment_index = records.len();
+    records.extend(build_fragment_index(&text.fragments));
+    let skeleton_index = records.len();
+    records.extend(build_skeleton_index(&text.skeletons, &text.fragments));
+    let ncx_index = records.len();
+    records.extend(build_ncx_index(&toc));
+
+    let first_resource_record = records.len();
+    let resource_count = resources.len();
+    records.extend(resources);
+
+    let fdst_record = records.len();
+    let mut fdst = b"FDST".to_vec();
+    push_u32(&mut fdst, 12);
+    push_u32(&mut fdst, 1);
+    push_u32(&mut fdst, 0);
+    push_u32(&mut fdst, text.text.len() as u32);
+    records.push(fdst);
+
+    let flis_record = records.len();
+    records.push(flis_record_data());
+    let fcis_record = records.len();
+    records.push(fcis_record_data(text.text.len()));
+    records.push(EOF_RECORD.to_vec());
+
+    let exth = build_exth(ExthMetadata {
+        author: &export.author,
+        title: &part.title,
+        language: &language,
+        series: part
+            .series_index
+            .map(|index| (export.title.as_str(), index)),
+        reading_direction: export.reading_direction,
+        cover_resource,
+        resource_count,
+        resolution,
+    });
+    records[0] = build_header_record(HeaderRecord {
+        part,
+        language: &language,
+        text_length: text.text.len(),
+        text_record_count,
+        first_non_text_record,
+        first_resource_record,
+        fdst_record,
+        flis_record,
+        fcis_record,
+        ncx_index,
+        fragment_index,
+        skeleton_index,
+        exth,
+    });
+
+    let export_base_dir = &get_export_base_dir();
+    std::fs::create_dir_all(export_base_dir)?;
+    fs::write(
+        part.get_temp_path(),
+        build_palm_database(&part.title, &records)?,
+    )?;
+    Ok(())
+}
+
+struct HeaderRecord<'a> {
+    part: &'a ExportPart,
+    language: &'a str,
+    text_length: usize,
+    text_record_count: usize,
+    first_non_text_record: usize,
+    first_resource_record: usize,
+    fdst_record: usize,
+    flis_record: usize,
+    fcis_record: usize,
+    ncx_index: usize,
+    fragment_index: usize,
+    skeleton_index: usize,
+    exth: Vec<u8>,
+}
+
+/// PalmDOC header, MOBI 8 header, EXTH block and the full title
+fn build_header_record(header: HeaderRecord) -> Vec<u8> {
+    const MOBI_HEADER_LENGTH: u32 = 264;
+    let title = header.part.title.as_bytes();
+    let title_offset = 16 + MOBI_HEADER_LENGTH as usize + header.exth.len();
+
+    let mut record = Vec::new();
+    // PalmDOC header: no compression, no encryption
+    push_u16(&mut record, 1);
+    push_u16(&mut record, 0);
+    push_u32(&mut record, header.text_length as u32);
+    push_u16(&mut record, header.text_record_count as u16);
+    push_u16(&mut record, TEXT_RECORD_SIZE as u16);
+    push_u16(&mut record, 0);
+    push_u16(&mut record, 0);
+
+    record.extend_from_slice(b"MOBI");
+    push_u32(&mut record, MOBI_HEADER_LENGTH);
+    push_u32(&mut record, 2); // book
+    push_u32(&mut record, 65001); // utf-8
+    push_u32(&mut record, part_uid(header.part));
+    push_u32(&mut record, 8); // file version
+    for _ in 0..10 {
+        // orthographic, inflection, index names, index keys, extra indices 0-5
+        push_u32(&mut record, NULL_INDEX);
+    }
+    push_u32(&mut record, header.first_non_text_record as u32);
+    push_u32(&mut record, title_offset as u32);
+    push_u32(&mut record, title.len() as u32);
+    push_u32(&mut record, mobi_locale(header.language));
+    push_u32(&mut record, 0);
+    push_u32(&mut record, 0);
+    push_u32(&mut record, 8); // min version
+    push_u32(&mut record, header.first_resource_record as u32);
+    for _ in 0..4 {
+        // huffman record offset, count, table offset, length
+        push_u32(&mut record, 0);
+    }
+    push_u32(&mut record, 0x50); // EXTH present
+    record.extend_from_slice(&[0; 32]);
+    push_u32(&mut record, NULL_INDEX);
+    push_u32(&mut record, NULL_INDEX); // DRM offset
+    push_u32(&mut record, 0);
+    push_u32(&mut record, 0);
+    push_u32(&mut record, 0);
+    record.extend_from_slice(&[0; 8]);
+    push_u32(&mut record, header.fdst_record as u32);
+    push_u32(&mut record, 1); // flow count
+    push_u32(&mut record, header.fcis_record as u32);
+    push_u32(&mut record, 1);
+    push_u32(&mut record, header.flis_record as u32);
+    push_u32(&mut record, 1);
+    record.extend_from_slice(&[0; 8]);
+    push_u32(&mut record, NULL_INDEX); // SRCS
+    push_u32(&mut record, 0);
+    record.extend_from_slice(&[0xFF; 8]);
+    push_u32(&mut record, 1); // extra data flags: multibyte trailing entries
+    push_u32(&mut record, header.ncx_index as u32);
+    push_u32(&mut record, header.fragment_index as u32);
+    push_u32(&mut record, header.skeleton_index as u32);
+    push_u32(&mut record, NULL_INDEX); // DATP
+    push_u32(&mut record, NULL_INDEX); // guide
+    push_u32(&mut record, NULL_INDEX);
+    push_u32(&mut record, 0);
+    push_u32(&mut record, NULL_INDEX);
+    push_u32(&mut record, 0);
+    debug_assert_eq!(record.len(), 16 + MOBI_HEADER_LENGTH as usize);
+
+    record.extend_from_slice(&header.exth);
+    record.extend_from_slice(title);
+    // Kindles expect some slack after the title
+    record.extend_from_slice(&[0; 2]);
+    let mut record = align(record);
+    record.extend_from_slice(&[0; 1024]);
+    record
+}
+
+struct ExthMetadata<'a> {
+    author: &'a str,
+    title: &'a str,
+    language: &'a str,
+    series: Option<(&'a str, i64)>,
+    reading_direction: ReadingDirection,
+    cover_resource: Option<usize>,
+    resource_count: usize,
+    resolution: Option<(u32, u32)>,
+}
+
+fn build_exth(metadata: ExthMetadata) -> Vec<u8> {
+    let mut entries: Vec<(u32, Vec<u8>)> = vec![
+        (100, metadata.author.as_bytes().to_vec()),
+        (503, metadata.title.as_bytes().to_vec()),
+        (524, metadata.language.as_bytes().to_vec()),
+        (501, b"EBOK".to_vec()),
+        (125, (metadata.resource_count as u32).to_be_bytes().to_vec()),
+        // fixed layout comic
+        (122, b"true".to_vec()),
+        (123, b"comic".to_vec()),
+        (124, b"portrait".to_vec()),
+        (127, b"true".to_vec()),
+        (128, b"true".to_vec()),
+        (132, b"false".to_vec()),
+    ];
+    // EXTH has no series record. Kindles sort the library by the title sort key when there is
+    // one, which keeps the parts in order and past "Part 9".
+    if let Some((series, index)) = metadata.series {
+        entries.push((508, format!("{} {:04}", series, index).into_bytes()));
+    }
+    if let Some((width, height)) = metadata.resolution {
+        entries.push((126, format!("{}x{}", width, height).into_bytes()));
+    }
+    if metadata.reading_direction == ReadingDirection::RightToLeft {
+        entries.push((525, b"horizontal-rl".to_vec()));
+        entries.push((527, b"rtl".to_vec()));
+    } else {
+        entries.push((525, b"horizontal-lr".to_vec()));
+        entries.push((527, b"ltr".to_vec()));
+    }
+    if let Some(cover_resource) = metadata.cover_resource {
+        entries.push((201, (cover_resource as u32).to_be_bytes().to_vec()));
+        entries.push((202, (cover_resource as u32).to_be_bytes().to_vec()));
+        entries.push((203, 0u32.to_be_bytes().to_vec()));
+        entries.push((
+            129,
+            format!("kindle:embed:{}", to_base32(cover_resource + 1, 4)).into_bytes(),
+        ));
+    }
+
+    let mut body = Vec::new();
+    for (kind, data) in entries.iter() {
+        push_u32(&mut body, *kind);
+        push_u32(&mut body, (data.len() + 8) as u32);
+        body.extend_from_slice(data);
+    }
+    let mut exth = b"EXTH".to_vec();
+    push_u32(&mut exth, (body.len() + 12) as u32);
+    push_u32(&mut exth, entries.len() as u32);
+    exth.extend_from_slice(&body);
+    align(exth)
+}
+
+fn build_skeleton_index(skeletons: &[Skeleton], fragments: &[Fragment]) -> Vec<Vec<u8>> {
+    let entries = skeletons
+        .iter()
+        .enumerate()
+        .map(|(number, skeleton)| {
+            let fragment_count = fragments
+                .iter()
+                .filter(|fragment| fragment.file_number == number)
+                .count();
+            // kindlegen writes every value twice, readers expect the same
+            (
+                format!("SKEL{:010}", number).into_bytes(),
+                encode_entry(
+                    SKELETON_TAGS,
+                    &[
+                        vec![fragment_count, fragment_count],
+                        vec![
+                            skeleton.start,
+                            skeleton.length,
+                            skeleton.start,
+                            skeleton.length,
+                        ],
+                    ],
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    build_index(SKELETON_TAGS, &entries, Vec::new())
+}
+
+fn build_fragment_index(fragments: &[Fragment]) -> Vec<Vec<u8>> {
+    let mut cncx = Cncx::default();
+    let mut file_offset = 0;
+    let entries = fragments
+        .iter()
+        .enumerate()
+        .map(|(sequence, fragment)| {
+            // fragments here are the only one in their file, so they start at 0 within it
+            if sequence > 0 && fragments[sequence - 1].file_number != fragment.file_number {
+                file_offset = 0;
+            }
+            let selector = cncx.add(&fragment.selector);
+            let entry = (
+                format!("{:010}", fragment.insert_position).into_bytes(),
+                encode_entry(
+                    FRAGMENT_TAGS,
+                    &[
+                        vec![selector],
+                        vec![fragment.file_number],
+                        vec![sequence],
+                        vec![file_offset, fragment.length],
+                    ],
+                ),
+            );
+            file_offset += fragment.length;
+            entry
+        })
+        .collect::<Vec<_>>();
+    build_index(FRAGMENT_TAGS, &entries, cncx.records())
+}
+
+fn build_ncx_index(toc: &[TocEntry]) -> Vec<Vec<u8>> {
+    let mut cncx = Cncx::default();
+    let entries = toc
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let label = cncx.add(&entry.title);
+            (
+                format!("{:04X}", index).into_bytes(),
+                encode_entry(
+                    NCX_TAGS,
+                    &[
+                        vec![entry.start],
+                        vec![entry.length],
+                        vec![label],
+                        vec![0],
+                        vec![entry.fragment_number, 0],
+                    ],
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    build_index(NCX_TAGS, &entries, cncx.records())
+}
+
+/// Control byte followed by the tag values. `values[i]` holds every value for the i-th
+/// non-terminal tag, a multiple of that tag's values per entry.
+fn encode_entry(tags: &[TagDefinition], values: &[Vec<usize>]) -> Vec<u8> {
+    let mut control_byte = 0u8;
+    let mut data = Vec::new();
+    for ((_, values_per_entry, mask, _), values) in tags.iter().filter(|t| t.3 == 0).zip(values) {
+        let count = (values.len() / *values_per_entry as usize) as u8;
+        control_byte |= mask & (count << mask.trailing_zeros());
+        for value in values {
+            data.extend(encode_forward_varint(*value));
+        }
+    }
+    let mut entry = vec![control_byte];
+    entry.extend(data);
+    entry
+}
+
+/// Builds the INDX header record, the INDX data records and any CNCX records that go with them
+fn build_index(
+    tags: &[TagDefinition],
+    entries: &[(Vec<u8>, Vec<u8>)],
+    cncx_records: Vec<Vec<u8>>,
+) -> Vec<Vec<u8>> {
+    // (entries, idxt offsets, last key)
+    let mut blocks: Vec<(Vec<u8>, Vec<u16>, Vec<u8>)> = vec![(Vec::new(), Vec::new(), Vec::new())];
+    for (key, tag_data) in entries {
+        let mut raw = vec![key.len() as u8];
+        raw.extend_from_slice(key);
+        raw.extend_from_slice(tag_data);
+
+        let (block, offsets, _) = blocks.last().unwrap();
+        if block.len() + offsets.len() * 2 + raw.len() + 2 > INDX_RECORD_LIMIT {
+            blocks.push((Vec::new(), Vec::new(), Vec::new()));
+        }
+        let (block, offsets, last_key) = blocks.last_mut().unwrap();
+        offsets.push((INDX_HEADER_LENGTH + block.len()) as u16);
+        block.extend(raw);
+        *last_key = key.clone();
+    }
+
+    let mut data_records = Vec::new();
+    for (block, offsets, _) in blocks.iter() {
+        let block = align(block.clone());
+        let mut idxt = b"IDXT".to_vec();
+        for offset in offsets {
+            idxt.extend_from_slice(&offset.to_be_bytes());
+        }
+        let idxt = align(idxt);
+
+        let mut record = b"INDX".to_vec();
+        push_u32(&mut record, INDX_HEADER_LENGTH as u32);
+        push_u32(&mut record, 0);
+        push_u32(&mut record, 1); // index record
+        push_u32(&mut record, 0);
+        push_u32(&mut record, (INDX_HEADER_LENGTH + block.len()) as u32);
+        push_u32(&mut record, offsets.len() as u32);
+        record.extend_from_slice(&[0xFF; 8]);
+        record.resize(INDX_HEADER_LENGTH, 0);
+        record.extend(block);
+        record.extend(idxt);
+        data_records.push(record);
+    }
+
+    let mut tagx = b"TAGX".to_vec();
+    push_u32(&mut tagx, 12 + 4 * tags.len() as u32);
+    push_u32(&mut tagx, 1); // control byte count
+    for (tag, values_per_entry, mask, end_flag) in tags {
+        tagx.extend_from_slice(&[*tag, *values_per_entry, *mask, *end_flag]);
+    }
+
+    // The header describes each data record by its last key and entry count
+    let mut geometry = Vec::new();
+    let mut geometry_idxt = b"IDXT".to_vec();
+    for (_, offsets, last_key) in blocks.iter() {
+        let position = INDX_HEADER_LENGTH + tagx.len() + geometry.len();
+        geometry_idxt.extend_from_slice(&(position as u16).to_be_bytes());
+        geometry.push(last_key.len() as u8);
+        geometry.extend_from_slice(last_key);
+        geometry.extend_from_slice(&(offsets.len() as u16).to_be_bytes());
+    }
+    let geometry = align(geometry);
+    let geometry_idxt = align(geometry_idxt);
+
+    let mut header = b"INDX".to_vec();
+    push_u32(&mut header, INDX_HEADER_LENGTH as u32);
+    push_u32(&mut header, 0);
+    push_u32(&mut header, 0); // index header
+    push_u32(&mut header, 2);
+    push_u32(
+        &mut header,
+        (INDX_HEADER_LENGTH + tagx.len() + geometry.len()) as u32,
+    );
+    push_u32(&mut header, data_records.len() as u32);
+    push_u32(&mut header, 65001);
+    push_u32(&mut header, NULL_INDEX);
+    push_u32(&mut header, entries.len() as u32);
+    push_u32(&mut header, 0); // ORDT
+    push_u32(&mut header, 0); // LIGT
+    push_u32(&mut header, 0);
+    push_u32(&mut header, cncx_records.len() as u32);
+    header.resize(180, 0);
+    push_u32(&mut header, INDX_HEADER_LENGTH as u32); // TAGX offset
+    header.resize(INDX_HEADER_LENGTH, 0);
+    header.extend(tagx);
+    header.extend(geometry);
+    header.extend(geometry_idxt);
+
+    let mut records = vec![header];
+    records.extend(data_records);
+    records.extend(cncx_records);
+    records
+}
+
+/// String table for index entries. Offsets past the first record are addressed as
+/// record number * 0x10000 + position.
+#[derive(Default)]
+struct Cncx {
+    records: Vec<Vec<u8>>,
+}
+
+impl Cncx {
+    fn add(&mut self, string: &str) -> usize {
+        let mut raw = encode_forward_varint(string.len());
+        raw.extend_from_slice(string.as_bytes());
+        if self
+            .records
+            .last()
+            .is_none_or(|record| record.len() + raw.len() > CNCX_RECORD_LIMIT)
+        {
+            self.records.push(Vec::new());
+        }
+        let record_number = self.records.len() - 1;
+        let record = self.records.last_mut().unwrap();
+        let offset = record_number * 0x10000 + record.len();
+        record.extend(raw);
+        offset
+    }
+
+    fn records(self) -> Vec<Vec<u8>> {
+        self.records.into_iter().map(align).collect()
+    }
+}
+
+fn build_palm_database(title: &str, records: &[Vec<u8>]) -> Result<Vec<u8>, AppError> {
+    // The record list is counted with a u16, anything past that can't be addressed
+    let record_count = u16::try_from(records.len()).map_err(|_| {
+        eyre!(
+            "Export needs {} records but azw3 is limited to {}, split it into smaller parts",
+            records.len(),
+            u16::MAX
+        )
+    })?;
+    let mut name: Vec<u8> = title
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == ' ')
+        .map(|c| if c == ' ' { b'_' } else { c as u8 })
+        .take(31)
+        .collect();
+    name.resize(32, 0);
+    let now = chrono::Utc::now().timestamp() as u32;
+
+    let mut pdb = name;
+    push_u16(&mut pdb, 0); // attributes
+    push_u16(&mut pdb, 0); // version
+    push_u32(&mut pdb, now);
+    push_u32(&mut pdb, now);
+    push_u32(&mut pdb, 0); // last backup
+    push_u32(&mut pdb, 0); // modification number
+    push_u32(&mut pdb, 0); // app info
+    push_u32(&mut pdb, 0); // sort info
+    pdb.extend_from_slice(b"BOOKMOBI");
+    push_u32(&mut pdb, (2 * records.len() - 1) as u32); // unique id seed
+    push_u32(&mut pdb, 0); // next record list
+    push_u16(&mut pdb, record_count);
+
+    let mut offset = pdb.len() + records.len() * 8 + 2;
+    for (index, record) in records.iter().enumerate() {
+        push_u32(&mut pdb, offset as u32);
+        let unique_id = (2 * index) as u32;
+        pdb.push(0);
+        pdb.extend_from_slice(&unique_id.to_be_bytes()[1..]);
+        offset += record.len();
+    }
+    push_u16(&mut pdb, 0);
+    for record in records {
+        pdb.extend_from_slice(record);
+    }
+    Ok(pdb)
+}
+
+fn flis_record_data() -> Vec<u8> {
+    let mut flis = b"FLIS".to_vec();
+    flis.extend_from_slice(&[
+        0, 0, 0, 8, 0, 0x41, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 1, 0, 3, 0, 0, 0, 3, 0,
+        0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF,
+    ]);
+    flis
+}
+
+fn fcis_record_data(text_length: usize) -> Vec<u8> {
+    let mut fcis = b"FCIS".to_vec();
+    fcis.extend_from_slice(&[0, 0, 0, 0x14, 0, 0, 0, 0x10, 0, 0, 0, 2, 0, 0, 0, 0]);
+    push_u32(&mut fcis, text_length as u32);
+    fcis.extend_from_slice(&[
+        0, 0, 0, 0, 0, 0, 0, 0x28, 0, 0, 0, 0, 0, 0, 0, 0x28, 0, 0, 0, 8, 0, 1, 0, 1, 0, 0, 0, 0,
+    ]);
+    fcis
+}
+
+/// Windows language id of a BCP 47 tag, which is what the MOBI header's locale field holds.
+/// Languages missing here are written as neutral.
+fn mobi_locale(language: &str) -> u32 {
+    let primary = language.split('-').next().unwrap_or_default();
+    match primary.to_ascii_lowercase().as_str() {
+        "ar" => 0x01,
+        "zh" => 0x04,
+        "cs" => 0x05,
+        "de" => 0x07,
+        "el" => 0x08,
+        "en" => 0x09,
+        "es" => 0x0A,
+        "fi" => 0x0B,
+        "fr" => 0x0C,
+        "he" => 0x0D,
+        "hu" => 0x0E,
+        "it" => 0x10,
+        "ja" => 0x11,
+        "ko" => 0x12,
+        "nl" => 0x13,
+        "pl" => 0x15,
+        "pt" => 0x16,
+        "ro" => 0x18,
+        "ru" => 0x19,
+        "sv" => 0x1D,
+        "th" => 0x1E,
+        "tr" => 0x1F,
+        "id" => 0x21,
+        "uk" => 0x22,
+        "vi" => 0x2A,
+        _ => 0,
+    }
+}
+
+/// Kindles use the UID to tell books apart, so it's derived from the same things as
+/// [`ExportPart::get_identifier`] and a re-export replaces the book instead of duplicating it
+fn part_uid(part: &ExportPart) -> u32 {
+    let key = ((part.export_id as u64) << 16) ^ part.part as u64;
+    (key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as u32
+}
+
+/// Variable width integer with the high bit set on the last byte
+fn encode_forward_varint(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7F) as u8 | 0x80];
+    value >>= 7;
+    while value > 0 {
+        bytes.insert(0, (value & 0x7F) as u8);
+        value >>= 7;
+    }
+    bytes
+}
+
+/// Zero padded base 32, the numbering used for `kindle:embed` links and `aid` attributes
+fn to_base32(mut value: usize, width: usize) -> String {
+    const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+    let mut digits = Vec::new();
+    loop {
+        digits.push(DIGITS[value % 32]);
+        value /= 32;
+        if value == 0 {
+            break;
+        }
+    }
+    while digits.len() < width {
+        digits.push(b'0');
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+fn align(mut data: Vec<u8>) -> Vec<u8> {
+    while !data.len().is_multiple_of(4) {
+        data.push(0);
+    }
+    data
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn test_part() -> ExportPart {
+        ExportPart {
+            id: 7,
+            export_id: 3,
+            part: 1,
+            title: "Test Manga".to_string(),
+            filename: "test.azw3".to_string(),
+            series_index: None,
+        }
+    }
+
+    fn test_exth(language: &str, cover_resource: Option<usize>) -> Vec<u8> {
+        build_exth(ExthMetadata {
+            author: "Author",
+            title: "Test Manga",
+            language,
+            series: Some(("Test", 3)),
+            reading_direction: ReadingDirection::RightToLeft,
+            cover_resource,
+            resource_count: 2,
+            resolution: Some((1072, 1448)),
+        })
+    }
+
+    /// (type, data) of every EXTH entry
+    fn parse_exth(exth: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let count = read_u32(exth, 8) as usize;
+        let mut offset = 12;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let length = read_u32(exth, offset + 4) as usize;
+            entries.push((
+                read_u32(exth, offset),
+                exth[offset + 8..offset + length].to_vec(),
+            ));
+            offset += length;
+        }
+        assert_eq!(offset, read_u32(exth, 4) as usize);
+        entries
+    }
+
+    #[test]
+    fn palm_database_record_table() {
+        let records = vec![vec![1; 3], vec![2; 5], vec![3; 8]];
+        let pdb = build_palm_database("Test: Manga", &records).unwrap();
+
+        assert_eq!(&pdb[..10], b"Test_Manga");
+        assert!(pdb[10..32].iter().all(|byte| *byte == 0));
+        assert_eq!(&pdb[60..68], b"BOOKMOBI");
+        assert_eq!(read_u32(&pdb, 68), 5);
+        assert_eq!(read_u16(&pdb, 76), 3);
+
+        let first_record = 78 + 3 * 8 + 2;
+        let mut offset = first_record;
+        for (index, record) in records.iter().enumerate() {
+            let entry = 78 + index * 8;
+            assert_eq!(read_u32(&pdb, entry) as usize, offset);
+            assert_eq!(read_u32(&pdb, entry + 4), 2 * index as u32);
+            assert_eq!(&pdb[offset..offset + record.len()], record.as_slice());
+            offset += record.len();
+        }
+        assert_eq!(read_u16(&pdb, first_record - 2), 0);
+        assert_eq!(pdb.len(), offset);
+    }
+
+    #[test]
+    fn palm_database_rejects_too_many_records() {
+        let records = vec![Vec::new(); u16::MAX as usize + 1];
+        assert!(build_palm_database("Test", &records).is_err());
+        let records = vec![Vec::new(); u16::MAX as usize];
+        assert!(build_palm_database("Test", &records).is_ok());
+    }
+
+    #[test]
+    fn header_record_offsets() {
+        let part = test_part();
+        let exth = test_exth("ja", None);
+        let exth_length = exth.len();
+        let record = build_header_record(HeaderRecord {
+            part: &part,
+            language: "ja",
+            text_length: 10_000,
+            text_record_count: 3,
+            first_non_text_record: 4,
+            first_resource_record: 10,
+            fdst_record: 12,
+            flis_record: 13,
+            fcis_record: 14,
+            ncx_index: 8,
+            fragment_index: 4,
+            skeleton_index: 6,
+            exth,
+        });
+
+        // PalmDOC header
+        assert_eq!(read_u16(&record, 0), 1);
+        assert_eq!(read_u32(&record, 4), 10_000);
+        assert_eq!(read_u16(&record, 8), 3);
+        assert_eq!(read_u16(&record, 10), TEXT_RECORD_SIZE as u16);
+
+        // MOBI header
+        assert_eq!(&record[16..20], b"MOBI");
+        assert_eq!(read_u32(&record, 20), 264);
+        assert_eq!(read_u32(&record, 28), 65001);
+        assert_eq!(read_u32(&record, 36), 8);
+        assert_eq!(read_u32(&record, 0x50), 4);
+        assert_eq!(read_u32(&record, 0x5C), 0x11);
+        assert_eq!(read_u32(&record, 0x6C), 10);
+        assert_eq!(read_u32(&record, 0x80), 0x50);
+        assert_eq!(read_u32(&record, 0xC0), 12);
+        assert_eq!(read_u32(&record, 0xC8), 14);
+        assert_eq!(read_u32(&record, 0xD0), 13);
+        assert_eq!(read_u32(&record, 0xF0), 1);
+        assert_eq!(read_u32(&record, 0xF4), 8);
+        assert_eq!(read_u32(&record, 0xF8), 4);
+        assert_eq!(read_u32(&record, 0xFC), 6);
+
+        // EXTH straight after the MOBI header, then the full title
+        assert_eq!(&record[280..284], b"EXTH");
+        let title_offset = read_u32(&record, 0x54) as usize;
+        let title_length = read_u32(&record, 0x58) as usize;
+        assert_eq!(title_offset, 280 + exth_length);
+        assert_eq!(
+            &record[title_offset..title_offset + title_length],
+            b"Test Manga"
+        );
+        assert_eq!(record.len() % 4, 0);
+    }
+
+    #[test]
+    fn exth_layout() {
+        let exth = test_exth("ko", Some(0));
+        assert_eq!(&exth[..4], b"EXTH");
+        assert_eq!(exth.len() % 4, 0);
+
+        let entries = parse_exth(&exth);
+        let entry = |kind: u32| {
+            entries
+                .iter()
+                .find(|(entry_kind, _)| *entry_kind == kind)
+                .map(|(_, data)| data.as_slice())
+        };
+        assert_eq!(entry(100), Some(b"Author".as_slice()));
+        assert_eq!(entry(503), Some(b"Test Manga".as_slice()));
+        assert_eq!(entry(524), Some(b"ko".as_slice()));
+        assert_eq!(entry(508), Some(b"Test 0003".as_slice()));
+        assert_eq!(entry(125), Some(2u32.to_be_bytes().as_slice()));
+        assert_eq!(entry(126), Some(b"1072x1448".as_slice()));
+        assert_eq!(entry(527), Some(b"rtl".as_slice()));
+        assert_eq!(entry(201), Some(0u32.to_be_bytes().as_slice()));
+        assert_eq!(entry(129), Some(b"kindle:embed:0001".as_slice()));
+    }
+
+    #[test]
+    fn exth_without_cover() {
+        let entries = parse_exth(&test_exth("en", None));
+        assert!(!entries
+            .iter()
+            .any(|(kind, _)| [201, 202, 129].contains(kind)));
+    }
+
+    #[test]
+    fn uid_survives_re_export() {
+        let part = test_part();
+        let re_exported = ExportPart {
+            id: 42,
+            ..test_part()
+        };
+        assert_eq!(part_uid(&part), part_uid(&re_exported));
+        let next_part = ExportPart {
+            part: 2,
+            ..test_part()
+        };
+        assert_ne!(part_uid(&part), part_uid(&next_part));
+    }
+
+    #[test]
+    fn locale_from_language() {
+        assert_eq!(mobi_locale("en"), 0x09);
+        assert_eq!(mobi_locale("ja"), 0x11);
+        assert_eq!(mobi_locale("zh-Hans"), 0x04);
+        assert_eq!(mobi_locale("pt-BR"), 0x16);
+        assert_eq!(mobi_locale("xx"), 0);
+    }
+}
@@ -1,119 +1,141 @@
 use std::{
     collections::HashSet,
-    env,
-    fs::{self, File},
-    io::{self, Seek, Write},
-    path::Path,
+    fs::File,
+    io::{Cursor, Write},
     sync::Arc,
 };
 
 use eyre::eyre;
-use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
 
-use crate::{models::export::{get_export_base_dir, Export}, suwayomi::get_chapters_by_ids, AppError};
-
-#[derive(Serialize, Deserialize)]
-struct CbzMetadata {
-    title: String,
-    author: String,
-    // Add other metadata fields as needed
-}
+use crate::{
+    models::{
+        export::{get_export_base_dir, Export, ReadingDirection},
+        export_part::ExportPart,
+    },
+    services::{control::check_interrupted, cover::get_cover_image, progress},
+    suwayomi::{get_chapters_by_ids, get_manga_by_id},
+    util::parse_volume_number,
+    AppError,
+};
 
-#[derive(Serialize, Deserialize)]
-struct CbzChapter {
-    id: i64,
-    title: String,
-}
+use super::{
+    comic_info::{ComicInfo, ComicPage, ComicPageType},
+    get_export_chapter_pages,
+};
 
 // TODO log events and errors
 pub async fn assemble_cbz(
     _pool: Arc<SqlitePool>,
     export: &Export,
+    part: &ExportPart,
     chapter_ids: &HashSet<i64>,
 ) -> Result<(), AppError> {
-    let chapter_base_dir = &env::var("CHAPTER_DL_PATH").unwrap_or("data/chapters".to_string());
     let export_base_dir = &get_export_base_dir();
-    std::fs::create_dir_all(&export_base_dir)?;
+    std::fs::create_dir_all(export_base_dir)?;
 
-    let output_path = export.get_path();
+    let chapters = match get_chapters_by_ids(chapter_ids).await? {
+        Some(chapters) => chapters.nodes,
+        None => return Err(eyre!("Chapters not found").into()),
+    };
+    let manga_id = match chapters.first() {
+        Some(chapter) => chapter.manga_id,
+        None => return Err(eyre!("Export has no chapters").into()),
+    };
+    let manga = get_manga_by_id(manga_id).await?;
 
+    let output_path = part.get_temp_path();
     let file = File::create(&output_path)?;
     let mut zip = ZipWriter::new(file);
 
-    let metadata = CbzMetadata {
-        title: export.title.to_owned(),
-        author: export.author.to_owned(),
-    };
-
-    // Write metadata
-    let metadata_json = serde_json::to_string(&metadata)?;
-    zip.start_file("metadata.json", SimpleFileOptions::default())?;
-    zip.write_all(metadata_json.as_bytes())?;
-
-    let chapters = match get_chapters_by_ids(&chapter_ids).await? {
-        Some(chapters) => chapters.nodes,
-        None => return Err(eyre!("Chapters not found").into()),
+    let volumes: HashSet<Option<i64>> = chapters
+        .iter()
+        .map(|chapter| parse_volume_number(&chapter.name))
+        .collect();
+    let mut comic_info = ComicInfo {
+        title: part.title.to_owned(),
+        series: manga.title,
+        number: match chapters.as_slice() {
+            [chapter] => Some(chapter.chapter_number.to_string()),
+            _ => None,
+        },
+        // Split exports number their parts, which readers group by series
+        volume: match volumes.into_iter().collect::<Vec<_>>().as_slice() {
+            [Some(volume)] => Some(*volume),
+            _ => part.series_index,
+        },
+        summary: manga.description,
+        writer: Some(export.author.to_owned()),
+        penciller: manga.artist,
+        genre: manga.genre,
+        language_iso: manga.source.map(|source| source.lang),
+        right_to_left: export.reading_direction == ReadingDirection::RightToLeft,
+        pages: Vec::new(),
     };
 
-    // Process chapters
-    for chapter in chapters {
-        let chapter_dir = Path::new(&chapter_base_dir).join(&chapter.id.to_string());
-        dbg!(&chapter_dir);
-
-        // Write chapter info
-        let cbz_chapter = CbzChapter {
-            id: chapter.id,
-            title: chapter.name,
-        };
-        let chapter_info = serde_json::to_string(&cbz_chapter)?;
+    // Pages are flattened into the archive root so every reader agrees on the page order.
+    // Chapters are numbered from 1 so the cover can sort ahead of them as 0000-0000
+    if let Some(cover) = get_cover_image(export, &chapters).await? {
+        let (image_width, image_height) = image::ImageReader::new(Cursor::new(&cover.data))
+            .with_guessed_format()?
+            .into_dimensions()?;
         zip.start_file(
-            format!("{}/info.json", chapter.id),
-            SimpleFileOptions::default(),
+            format!("0000-0000.{}", cover.extension),
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
         )?;
-        zip.write_all(chapter_info.as_bytes())?;
-
-        // Add chapter images
-        add_directory_to_zip(&mut zip, &chapter_dir, &chapter.id.to_string())?;
+        zip.write_all(&cover.data)?;
+        comic_info.pages.push(ComicPage {
+            page_type: ComicPageType::FrontCover,
+            image_size: cover.data.len() as u64,
+            image_width,
+            image_height,
+            bookmark: None,
+        });
     }
 
-    zip.finish()?;
-    println!("CBZ file created: {:?}", output_path);
-    Ok(())
-}
+    for (chapter_index, chapter) in chapters.iter().enumerate() {
+        for (page_index, page) in get_export_chapter_pages(export.id, chapter.id)?
+            .iter()
+            .enumerate()
+        {
+            check_interrupted(export.id)?;
+            progress::advance(export.id, 1);
+            let extension = page
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .ok_or(eyre!("Page {:?} has no extension", page))?;
+            let image_data = std::fs::read(page)?;
+            let (image_width, image_height) = image::image_dimensions(page)?;
 
-fn add_directory_to_zip<T: Write + Seek>(
-    zip: &mut ZipWriter<T>,
-    dir_path: &Path,
-    zip_path: &str,
-) -> io::Result<()> {
-    for entry in fs::read_dir(dir_path)? {
-        dbg!(&entry);
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_dir() {
-            println!("path is dir {:?}", path);
-            add_directory_to_zip(
-                zip,
-                &path,
-                &format!(
-                    "{}/{}",
-                    zip_path,
-                    path.file_name().unwrap().to_str().unwrap()
-                ),
-            )?;
-        } else {
-            let mut file = File::open(&path)?;
-            dbg!(&file);
-            let file_name = path.file_name().unwrap().to_str().unwrap();
             zip.start_file(
-                format!("{}/{}", zip_path, file_name),
+                format!("{:04}-{:04}.{}", chapter_index + 1, page_index, extension),
                 SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
             )?;
-            io::copy(&mut file, zip)?;
+            zip.write_all(&image_data)?;
+
+            comic_info.pages.push(ComicPage {
+                page_type: if comic_info.pages.is_empty() {
+                    ComicPageType::FrontCover
+                } else {
+                    ComicPageType::Story
+                },
+                image_size: image_data.len() as u64,
+                image_width,
+                image_height,
+                bookmark: if page_index == 0 {
+                    Some(chapter.name.to_owned())
+                } else {
+                    None
+                },
+            });
         }
     }
+
+    zip.start_file("ComicInfo.xml", SimpleFileOptions::default())?;
+    zip.write_all(comic_info.to_xml().as_bytes())?;
+
+    zip.finish()?;
+    println!("CBZ file created: {:?}", output_path);
     Ok(())
 }
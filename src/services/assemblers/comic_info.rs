@@ -0,0 +1,175 @@
+use std::fmt::Write;
+
+use crate::util::escape_xml;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComicPageType {
+    FrontCover,
+    Story,
+}
+
+impl std::fmt::Display for ComicPageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComicPageType::FrontCover => write!(f, "FrontCover"),
+            ComicPageType::Story => write!(f, "Story"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ComicPage {
+    pub page_type: ComicPageType,
+    pub image_size: u64,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub bookmark: Option<String>,
+}
+
+/// Subset of the ComicInfo.xml schema (Anansi v2.0) that we can fill in from Suwayomi
+#[derive(Debug, Default)]
+pub struct ComicInfo {
+    pub title: String,
+    pub series: String,
+    pub number: Option<String>,
+    pub volume: Option<i64>,
+    pub summary: Option<String>,
+    pub writer: Option<String>,
+    pub penciller: Option<String>,
+    pub genre: Vec<String>,
+    pub language_iso: Option<String>,
+    pub right_to_left: bool,
+    pub pages: Vec<ComicPage>,
+}
+
+impl ComicInfo {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n");
+
+        push_element(&mut xml, "Title", Some(&self.title));
+        push_element(&mut xml, "Series", Some(&self.series));
+        push_element(&mut xml, "Number", self.number.as_deref());
+        push_element(
+            &mut xml,
+            "Volume",
+            self.volume.map(|v| v.to_string()).as_deref(),
+        );
+        push_element(&mut xml, "Summary", self.summary.as_deref());
+        push_element(&mut xml, "Writer", self.writer.as_deref());
+        push_element(&mut xml, "Penciller", self.penciller.as_deref());
+        if !self.genre.is_empty() {
+            push_element(&mut xml, "Genre", Some(&self.genre.join(", ")));
+        }
+        push_element(&mut xml, "LanguageISO", self.language_iso.as_deref());
+        push_element(&mut xml, "PageCount", Some(&self.pages.len().to_string()));
+        push_element(
+            &mut xml,
+            "Manga",
+            Some(if self.right_to_left {
+                "YesAndRightToLeft"
+            } else {
+                "Yes"
+            }),
+        );
+
+        xml.push_str("  <Pages>\n");
+        for (index, page) in self.pages.iter().enumerate() {
+            let _ = write!(
+                xml,
+                "    <Page Image=\"{}\" Type=\"{}\" ImageSize=\"{}\" ImageWidth=\"{}\" ImageHeight=\"{}\"",
+                index, page.page_type, page.image_size, page.image_width, page.image_height
+            );
+            if let Some(bookmark) = &page.bookmark {
+                let _ = write!(xml, " Bookmark=\"{}\"", escape_xml(bookmark));
+            }
+            xml.push_str(" />\n");
+        }
+        xml.push_str("  </Pages>\n");
+
+        xml.push_str("</ComicInfo>\n");
+        xml
+    }
+}
+
+fn push_element(xml: &mut String, name: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        if !value.is_empty() {
+            let _ = writeln!(xml, "  <{}>{}</{}>", name, escape_xml(value), name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(page_type: ComicPageType, bookmark: Option<&str>) -> ComicPage {
+        ComicPage {
+            page_type,
+            image_size: 1024,
+            image_width: 800,
+            image_height: 1200,
+            bookmark: bookmark.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn escapes_text_and_attributes() {
+        let info = ComicInfo {
+            title: "Tom & Jerry <Special>".to_string(),
+            series: "\"Quoted\" 'Series'".to_string(),
+            pages: vec![page(ComicPageType::Story, Some("Ch. 1 & 2"))],
+            ..Default::default()
+        };
+        let xml = info.to_xml();
+        assert!(xml.contains("<Title>Tom &amp; Jerry &lt;Special&gt;</Title>"));
+        assert!(xml.contains("<Series>&quot;Quoted&quot; &apos;Series&apos;</Series>"));
+        assert!(xml.contains("Bookmark=\"Ch. 1 &amp; 2\""));
+    }
+
+    #[test]
+    fn marks_the_reading_direction() {
+        let mut info = ComicInfo::default();
+        assert!(info.to_xml().contains("<Manga>Yes</Manga>"));
+        info.right_to_left = true;
+        assert!(info.to_xml().contains("<Manga>YesAndRightToLeft</Manga>"));
+    }
+
+    #[test]
+    fn skips_empty_elements() {
+        let info = ComicInfo {
+            title: "Title".to_string(),
+            summary: Some(String::new()),
+            ..Default::default()
+        };
+        let xml = info.to_xml();
+        assert!(!xml.contains("<Series>"));
+        assert!(!xml.contains("<Summary>"));
+        assert!(!xml.contains("<Genre>"));
+        assert!(!xml.contains("<Volume>"));
+    }
+
+    #[test]
+    fn lists_every_page() {
+        let info = ComicInfo {
+            pages: vec![
+                page(ComicPageType::FrontCover, None),
+                page(ComicPageType::Story, Some("Chapter 1")),
+                page(ComicPageType::Story, None),
+            ],
+            ..Default::default()
+        };
+        let xml = info.to_xml();
+        assert!(xml.contains("<PageCount>3</PageCount>"));
+        assert!(xml.contains(
+            "<Page Image=\"0\" Type=\"FrontCover\" ImageSize=\"1024\" ImageWidth=\"800\" ImageHeight=\"1200\" />"
+        ));
+        assert!(xml.contains(
+            "<Page Image=\"1\" Type=\"Story\" ImageSize=\"1024\" ImageWidth=\"800\" ImageHeight=\"1200\" Bookmark=\"Chapter 1\" />"
+        ));
+        assert!(xml.contains("<Page Image=\"2\" Type=\"Story\""));
+        assert_eq!(xml.matches("Type=\"FrontCover\"").count(), 1);
+    }
+}
@@ -1,64 +1,66 @@
 use std::{
     collections::HashSet,
-    env,
-    fs::{self, File},
-    io::Cursor,
-    path::Path,
+    fs,
+    io::{Cursor, Read, Write},
     sync::Arc,
 };
 
-use epub_builder::{EpubBuilder, EpubContent, ZipLibrary};
+use epub_builder::{EpubBuilder, EpubContent, EpubVersion, ReferenceType, ZipLibrary};
 use eyre::eyre;
 use sqlx::SqlitePool;
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
 
 use crate::{
     models::{
-        export::{get_export_base_dir, Export},
+        export::{get_export_base_dir, Export, ReadingDirection},
         export_log::log_export_step,
+        export_part::ExportPart,
     },
+    services::{control::check_interrupted, cover::get_cover_image, progress},
     suwayomi::get_chapters_by_ids,
     AppError,
 };
 
+use super::{fixed_epub::render_series_meta, get_export_chapter_pages};
+
 pub async fn assemble_epub(
     pool: Arc<SqlitePool>,
     export: &Export,
+    part: &ExportPart,
     chapter_ids: &HashSet<i64>,
 ) -> Result<(), AppError> {
-    let chapter_base_dir = &env::var("CHAPTER_DL_PATH").unwrap_or("data/chapters".to_string());
     let mut epub = EpubBuilder::new(ZipLibrary::new()?)?;
-    epub.metadata("title", &export.title)?;
+    // page-progression-direction only exists in the EPUB 3 spine
+    epub.epub_version(EpubVersion::V30);
+    epub.metadata("title", &part.title)?;
     epub.metadata("author", &export.author)?;
+    if export.reading_direction == ReadingDirection::RightToLeft {
+        epub.metadata("direction", "rtl")?;
+    }
 
-    let chapters = match get_chapters_by_ids(&chapter_ids).await? {
+    let chapters = match get_chapters_by_ids(chapter_ids).await? {
         Some(chapters) => chapters.nodes,
         None => return Err(eyre!("Chapters not found").into()),
     };
+
+    if let Some(cover) = get_cover_image(export, &chapters).await? {
+        let file_name = format!("cover.{}", cover.extension);
+        let mime_type = cover.mime_type();
+        epub.add_cover_image(&file_name, Cursor::new(cover.data), mime_type)?;
+        let cover_content = format!(
+            "<?xml version='1.0' encoding='utf-8'?>\n<html xmlns=\"http://www.w3.org/1999/xhtml\">\n<head/><body>\n<img src=\"{}\" alt=\"Cover\" />\n</body>\n</html>",
+            file_name
+        );
+        epub.add_content(
+            EpubContent::new("cover.xhtml", Cursor::new(cover_content))
+                .title("Cover")
+                .reftype(ReferenceType::Cover),
+        )?;
+    }
+
     // Add chapters
     for chapter in chapters {
-        let chapter_dir = Path::new(&chapter_base_dir).join(&chapter.id.to_string());
-        dbg!(&chapter_dir);
-        let mut pages = Vec::new();
-
-        // Read image files from the chapter directory
-        for entry in fs::read_dir(chapter_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                pages.push(path);
-            }
-        }
-
-        // TODO improve error handling
-        pages.sort_by_key(|page_path| {
-            page_path
-                .file_stem()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .parse::<i32>()
-                .unwrap()
-        });
+        let pages = get_export_chapter_pages(export.id, chapter.id)?;
 
         // Create chapter content
         let mut chapter_content = String::new();
@@ -66,6 +68,8 @@ pub async fn assemble_epub(
         chapter_content.push_str(&format!("<h1>{}</h1>\n", chapter.name));
 
         for page in pages {
+            check_interrupted(export.id)?;
+            progress::advance(export.id, 1);
             let image_data = fs::read(&page)?;
             let og_file_name = page.file_name().unwrap().to_str().unwrap();
             let mime_type = format!("image/{}", page.extension().unwrap().to_str().unwrap());
@@ -81,7 +85,7 @@ pub async fn assemble_epub(
             EpubContent::new(&chapter.name, Cursor::new(chapter_content)).title(&chapter.name),
         )?;
         log_export_step(
-            &*pool,
+            &pool,
             export.id,
             export.step,
             format!("Added chapter {} to epub", chapter.id).as_str(),
@@ -91,9 +95,63 @@ pub async fn assemble_epub(
     }
 
     let epub_base_dir = &get_export_base_dir();
-    std::fs::create_dir_all(&epub_base_dir)?;
+    std::fs::create_dir_all(epub_base_dir)?;
     // Generate EPUB file
-    let mut output_file = File::create(export.get_path())?;
-    epub.generate(&mut output_file)?;
+    let mut output = Vec::new();
+    epub.generate(&mut output)?;
+    if let Some(index) = part.series_index {
+        output = add_series_meta(output, &export.title, index)?;
+    }
+    fs::write(part.get_temp_path(), output)?;
     Ok(())
 }
+
+/// epub-builder has no way to add arbitrary metadata, so the series is written into the
+/// generated package document, with every other entry copied over untouched
+fn add_series_meta(epub: Vec<u8>, series: &str, index: i64) -> Result<Vec<u8>, AppError> {
+    let mut archive = ZipArchive::new(Cursor::new(epub))?;
+    let mut output = ZipWriter::new(Cursor::new(Vec::new()));
+    for file_index in 0..archive.len() {
+        let mut file = archive.by_index(file_index)?;
+        if file.name() != "OEBPS/content.opf" {
+            output.raw_copy_file(file)?;
+            continue;
+        }
+        let mut opf = String::new();
+        file.read_to_string(&mut opf)?;
+        let opf = opf.replacen(
+            "</metadata>",
+            &format!("{}</metadata>", render_series_meta(series, index)),
+            1,
+        );
+        output.start_file("OEBPS/content.opf", SimpleFileOptions::default())?;
+        output.write_all(opf.as_bytes())?;
+    }
+    Ok(output.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_meta_is_added_to_the_package() {
+        let mut epub = EpubBuilder::new(ZipLibrary::new().unwrap()).unwrap();
+        epub.epub_version(EpubVersion::V30);
+        epub.metadata("title", "Test - Part 2").unwrap();
+        let mut output = Vec::new();
+        epub.generate(&mut output).unwrap();
+
+        let output = add_series_meta(output, "Test", 2).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(output)).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+        let mut opf = String::new();
+        archive
+            .by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        assert!(opf.contains(r#"<meta name="calibre:series_index" content="2"/>"#));
+        assert!(opf.find("group-position").unwrap() < opf.find("</metadata>").unwrap());
+    }
+}
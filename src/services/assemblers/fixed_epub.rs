@@ -0,0 +1,520 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{Cursor, Write},
+    sync::Arc,
+};
+
+use eyre::eyre;
+use sqlx::SqlitePool;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    models::{
+        export::{get_export_base_dir, Export, ReadingDirection},
+        export_log::log_export_step,
+        export_part::ExportPart,
+    },
+    services::{control::check_interrupted, cover::get_cover_image, progress},
+    suwayomi::get_chapters_by_ids,
+    util::escape_xml,
+    AppError,
+};
+
+use super::{get_export_chapter_pages, get_manga_language};
+
+struct FixedPage {
+    id: String,
+    cover: bool,
+    image_href: String,
+    image_mime_type: String,
+    width: u32,
+    height: u32,
+}
+
+struct TocEntry {
+    title: String,
+    href: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EpubFlavor {
+    Standard,
+    /// KEPUB, with the markup and metadata Kobo's renderer expects
+    Kobo,
+}
+
+/// Writes an EPUB 3 with `rendition:layout` set to `pre-paginated`, one XHTML document per
+/// page image with a viewport that matches the image, instead of going through epub-builder
+/// which only knows how to produce reflowable books.
+pub async fn assemble_fixed_layout_epub(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    part: &ExportPart,
+    chapter_ids: &HashSet<i64>,
+) -> Result<(), AppError> {
+    write_fixed_layout_epub(pool, export, part, chapter_ids, EpubFlavor::Standard).await
+}
+
+pub async fn write_fixed_layout_epub(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    part: &ExportPart,
+    chapter_ids: &HashSet<i64>,
+    flavor: EpubFlavor,
+) -> Result<(), AppError> {
+    let chapters = match get_chapters_by_ids(chapter_ids).await? {
+        Some(chapters) => chapters.nodes,
+        None => return Err(eyre!("Chapters not found").into()),
+    };
+    let language = match chapters.first() {
+        Some(chapter) => get_manga_language(chapter.manga_id).await?,
+        None => return Err(eyre!("Export has no chapters").into()),
+    };
+
+    let epub_base_dir = &get_export_base_dir();
+    std::fs::create_dir_all(epub_base_dir)?;
+    let file = File::create(part.get_temp_path())?;
+    let mut zip = ZipWriter::new(file);
+
+    // The mimetype entry has to come first and must not be compressed
+    zip.start_file(
+        "mimetype",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", SimpleFileOptions::default())?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+    if flavor == EpubFlavor::Kobo {
+        zip.start_file(
+            "META-INF/com.apple.ibooks.display-options.xml",
+            SimpleFileOptions::default(),
+        )?;
+        zip.write_all(DISPLAY_OPTIONS_XML.as_bytes())?;
+    }
+
+    let mut pages: Vec<FixedPage> = Vec::new();
+    let mut toc: Vec<TocEntry> = Vec::new();
+
+    if let Some(cover) = get_cover_image(export, &chapters).await? {
+        let (width, height) = image::ImageReader::new(Cursor::new(&cover.data))
+            .with_guessed_format()?
+            .into_dimensions()?;
+        let image_href = format!("images/cover.{}", cover.extension);
+        zip.start_file(
+            format!("OEBPS/{}", image_href),
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(&cover.data)?;
+        zip.start_file("OEBPS/pages/cover.xhtml", SimpleFileOptions::default())?;
+        zip.write_all(
+            render_page(
+                "Cover",
+                &format!("../{}", image_href),
+                width,
+                height,
+                flavor,
+            )
+            .as_bytes(),
+        )?;
+        pages.push(FixedPage {
+            id: "cover".to_string(),
+            cover: true,
+            image_href,
+            image_mime_type: cover.mime_type(),
+            width,
+            height,
+        });
+    }
+
+    for (chapter_index, chapter) in chapters.iter().enumerate() {
+        for (page_index, page) in get_export_chapter_pages(export.id, chapter.id)?
+            .iter()
+            .enumerate()
+        {
+            check_interrupted(export.id)?;
+            progress::advance(export.id, 1);
+            let extension = page
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .ok_or(eyre!("Page {:?} has no extension", page))?;
+            let (width, height) = image::image_dimensions(page)?;
+            let id = format!("p{:04}-{:04}", chapter_index, page_index);
+            let image_href = format!("images/{}.{}", id, extension);
+
+            zip.start_file(
+                format!("OEBPS/{}", image_href),
+                SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+            )?;
+            zip.write_all(&fs::read(page)?)?;
+
+            zip.start_file(
+                format!("OEBPS/pages/{}.xhtml", id),
+                SimpleFileOptions::default(),
+            )?;
+            zip.write_all(
+                render_page(
+                    &chapter.name,
+                    &format!("../{}", image_href),
+                    width,
+                    height,
+                    flavor,
+                )
+                .as_bytes(),
+            )?;
+
+            if page_index == 0 {
+                toc.push(TocEntry {
+                    title: chapter.name.to_owned(),
+                    href: format!("pages/{}.xhtml", id),
+                });
+            }
+            pages.push(FixedPage {
+                id,
+                cover: false,
+                image_href,
+                image_mime_type: format!("image/{}", extension),
+                width,
+                height,
+            });
+        }
+        log_export_step(
+            &pool,
+            export.id,
+            export.step,
+            format!("Added chapter {} to epub", chapter.id).as_str(),
+        )
+        .await?;
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", SimpleFileOptions::default())?;
+    zip.write_all(render_nav(&part.title, &toc).as_bytes())?;
+    zip.start_file("OEBPS/toc.ncx", SimpleFileOptions::default())?;
+    zip.write_all(render_ncx(part, &toc).as_bytes())?;
+    zip.start_file("OEBPS/content.opf", SimpleFileOptions::default())?;
+    zip.write_all(
+        render_opf(
+            OpfMetadata {
+                uid: &part.get_identifier(),
+                title: &part.title,
+                author: &export.author,
+                language: &language,
+                series: part
+                    .series_index
+                    .map(|index| (export.title.as_str(), index)),
+                reading_direction: export.reading_direction,
+            },
+            &pages,
+        )
+        .as_bytes(),
+    )?;
+
+    zip.finish()?;
+    Ok(())
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+/// Kobo's renderer only switches to fixed layout for books that carry the iBooks display
+/// options, the `rendition:` properties alone aren't enough on older firmware
+const DISPLAY_OPTIONS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<display_options>
+  <platform name="*">
+    <option name="fixed-layout">true</option>
+    <option name="open-to-spread">false</option>
+  </platform>
+</display_options>
+"#;
+
+fn render_page(
+    title: &str,
+    image_href: &str,
+    width: u32,
+    height: u32,
+    flavor: EpubFlavor,
+) -> String {
+    let image = format!("<img src=\"{}\" alt=\"\"/>", escape_xml(image_href));
+    // Kobo only treats content wrapped in koboSpans as part of the book, kepubify style
+    let body = match flavor {
+        EpubFlavor::Standard => image,
+        EpubFlavor::Kobo => format!(
+            "<div id=\"book-columns\"><div id=\"book-inner\"><span class=\"koboSpan\" id=\"kobo.1.1\">{}</span></div></div>",
+            image
+        ),
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>html, body {{ margin: 0; padding: 0; width: {width}px; height: {height}px; }} img {{ display: block; width: {width}px; height: {height}px; }}</style>
+</head>
+<body>
+  {body}
+</body>
+</html>
+"#,
+        title = escape_xml(title),
+        body = body,
+        width = width,
+        height = height,
+    )
+}
+
+fn render_nav(title: &str, toc: &[TocEntry]) -> String {
+    let entries: String = toc
+        .iter()
+        .map(|entry| {
+            format!(
+                "      <li><a href=\"{}\">{}</a></li>\n",
+                escape_xml(&entry.href),
+                escape_xml(&entry.title)
+            )
+        })
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+</head>
+<body>
+  <nav epub:type="toc" id="toc">
+    <ol>
+{entries}    </ol>
+  </nav>
+</body>
+</html>
+"#,
+        title = escape_xml(title),
+        entries = entries,
+    )
+}
+
+fn render_ncx(part: &ExportPart, toc: &[TocEntry]) -> String {
+    let nav_points: String = toc
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            format!(
+                "    <navPoint id=\"nav-{index}\" playOrder=\"{order}\"><navLabel><text>{title}</text></navLabel><content src=\"{href}\"/></navPoint>\n",
+                index = index,
+                order = index + 1,
+                title = escape_xml(&entry.title),
+                href = escape_xml(&entry.href),
+            )
+        })
+        .collect();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <head>
+    <meta name="dtb:uid" content="{uid}"/>
+  </head>
+  <docTitle><text>{title}</text></docTitle>
+  <navMap>
+{nav_points}  </navMap>
+</ncx>
+"#,
+        uid = escape_xml(&part.get_identifier()),
+        title = escape_xml(&part.title),
+        nav_points = nav_points,
+    )
+}
+
+/// EPUB 3 collections plus the calibre metadata most readers still look for
+pub(super) fn render_series_meta(series: &str, index: i64) -> String {
+    format!(
+        r##"    <meta property="belongs-to-collection" id="series">{series}</meta>
+    <meta refines="#series" property="collection-type">series</meta>
+    <meta refines="#series" property="group-position">{index}</meta>
+    <meta name="calibre:series" content="{series}"/>
+    <meta name="calibre:series_index" content="{index}"/>
+"##,
+        series = escape_xml(series),
+        index = index,
+    )
+}
+
+/// Everything the package document needs besides the pages
+struct OpfMetadata<'a> {
+    uid: &'a str,
+    title: &'a str,
+    author: &'a str,
+    language: &'a str,
+    series: Option<(&'a str, i64)>,
+    reading_direction: ReadingDirection,
+}
+
+fn render_opf(meta: OpfMetadata, pages: &[FixedPage]) -> String {
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    let mut cover_meta = String::new();
+    for page in pages {
+        manifest.push_str(&format!(
+            "    <item id=\"{id}\" href=\"pages/{id}.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+            id = page.id,
+        ));
+        manifest.push_str(&format!(
+            "    <item id=\"img-{id}\" href=\"{href}\" media-type=\"{mime}\"{properties}/>\n",
+            id = page.id,
+            href = escape_xml(&page.image_href),
+            mime = escape_xml(&page.image_mime_type),
+            properties = if page.cover {
+                " properties=\"cover-image\""
+            } else {
+                ""
+            },
+        ));
+        if page.cover {
+            cover_meta = format!("    <meta name=\"cover\" content=\"img-{}\"/>\n", page.id);
+        }
+        spine.push_str(&format!("    <itemref idref=\"{}\"/>\n", page.id));
+    }
+    let resolution = pages
+        .first()
+        .map(|page| format!("{}x{}", page.width, page.height))
+        .unwrap_or_default();
+    let series_meta = match meta.series {
+        Some((series, index)) => render_series_meta(series, index),
+        None => String::new(),
+    };
+    let modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" xmlns="http://www.idpf.org/2007/opf" unique-identifier="book-id" prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">{uid}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:creator>{author}</dc:creator>
+    <dc:language>{language}</dc:language>
+    <meta property="dcterms:modified">{modified}</meta>
+    <meta property="rendition:layout">pre-paginated</meta>
+    <meta property="rendition:orientation">auto</meta>
+    <meta property="rendition:spread">landscape</meta>
+    <meta name="fixed-layout" content="true"/>
+    <meta name="original-resolution" content="{resolution}"/>
+{series_meta}{cover_meta}  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+{manifest}  </manifest>
+  <spine toc="ncx" page-progression-direction="{direction}">
+{spine}  </spine>
+</package>
+"#,
+        uid = escape_xml(meta.uid),
+        title = escape_xml(meta.title),
+        author = escape_xml(meta.author),
+        language = escape_xml(meta.language),
+        modified = modified,
+        resolution = resolution,
+        series_meta = series_meta,
+        cover_meta = cover_meta,
+        direction = match meta.reading_direction {
+            ReadingDirection::LeftToRight => "ltr",
+            ReadingDirection::RightToLeft => "rtl",
+        },
+        manifest = manifest,
+        spine = spine,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pages() -> Vec<FixedPage> {
+        vec![
+            FixedPage {
+                id: "cover".to_string(),
+                cover: true,
+                image_href: "images/cover.jpg".to_string(),
+                image_mime_type: "image/jpeg".to_string(),
+                width: 1072,
+                height: 1448,
+            },
+            FixedPage {
+                id: "p0000-0000".to_string(),
+                cover: false,
+                image_href: "images/p0000-0000.png".to_string(),
+                image_mime_type: "image/png".to_string(),
+                width: 1072,
+                height: 1448,
+            },
+        ]
+    }
+
+    fn test_opf(reading_direction: ReadingDirection) -> String {
+        render_opf(
+            OpfMetadata {
+                uid: "urn:uuid:test",
+                title: "Test - Part 2",
+                author: "Author",
+                language: "ja",
+                series: Some(("Test", 2)),
+                reading_direction,
+            },
+            &test_pages(),
+        )
+    }
+
+    #[test]
+    fn kobo_opf_uses_the_rendition_properties() {
+        let opf = test_opf(ReadingDirection::RightToLeft);
+        assert!(opf.contains(r#"<meta property="rendition:layout">pre-paginated</meta>"#));
+        assert!(opf.contains(r#"<meta property="rendition:spread">landscape</meta>"#));
+        assert!(opf.contains(r#"<meta property="rendition:orientation">auto</meta>"#));
+        assert!(opf.contains(r#"<spine toc="ncx" page-progression-direction="rtl">"#));
+        assert!(opf.contains("<dc:language>ja</dc:language>"));
+        assert!(opf.contains(r#"<meta name="cover" content="img-cover"/>"#));
+        assert!(opf.contains(r##"<meta refines="#series" property="group-position">2</meta>"##));
+        for kindle_key in [
+            "book-type",
+            "primary-writing-mode",
+            "zero-gutter",
+            "zero-margin",
+            "ke-border",
+            "orientation-lock",
+            "region-mag",
+        ] {
+            assert!(!opf.contains(kindle_key), "{} in the OPF", kindle_key);
+        }
+
+        let opf = test_opf(ReadingDirection::LeftToRight);
+        assert!(opf.contains(r#"page-progression-direction="ltr""#));
+    }
+
+    #[test]
+    fn kobo_pages_are_wrapped_in_kobo_spans() {
+        let page = render_page("Chapter 1", "../images/p.png", 800, 1200, EpubFlavor::Kobo);
+        assert!(page.contains(
+            r#"<span class="koboSpan" id="kobo.1.1"><img src="../images/p.png" alt=""/></span>"#
+        ));
+        assert!(page.contains(r#"<meta name="viewport" content="width=800, height=1200"/>"#));
+
+        let page = render_page(
+            "Chapter 1",
+            "../images/p.png",
+            800,
+            1200,
+            EpubFlavor::Standard,
+        );
+        assert!(!page.contains("koboSpan"));
+    }
+
+    #[test]
+    fn display_options_enable_fixed_layout() {
+        assert!(DISPLAY_OPTIONS_XML.contains(r#"<option name="fixed-layout">true</option>"#));
+    }
+}
@@ -0,0 +1,21 @@
+use std::{collections::HashSet, sync::Arc};
+
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{export::Export, export_part::ExportPart},
+    AppError,
+};
+
+use super::fixed_epub::{write_fixed_layout_epub, EpubFlavor};
+
+/// Kobo's KEPUB renderer is much faster than its plain EPUB one and honours fixed layout,
+/// so KEPUB exports are always one page per image
+pub async fn assemble_kepub(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    part: &ExportPart,
+    chapter_ids: &HashSet<i64>,
+) -> Result<(), AppError> {
+    write_fixed_layout_epub(pool, export, part, chapter_ids, EpubFlavor::Kobo).await
+}
@@ -1,2 +1,102 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::eyre;
+
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{
+        chapter_cache::touch_cached_chapter,
+        chapter_page::{get_chapter_manifest, PageStatus},
+    },
+    services::processing::get_processed_chapter_dir,
+    suwayomi::get_manga_by_id,
+    AppError,
+};
+
+pub mod azw3;
+pub mod cbz;
+mod comic_info;
 pub mod epub;
-pub mod cbz;
\ No newline at end of file
+pub mod fixed_epub;
+pub mod kepub;
+pub mod pdf;
+
+pub fn get_chapter_dir(chapter_id: i64) -> PathBuf {
+    let chapter_base_dir = env::var("CHAPTER_DL_PATH").unwrap_or("data/chapters".to_string());
+    Path::new(&chapter_base_dir).join(chapter_id.to_string())
+}
+
+/// The downloaded page images of a chapter in reading order, taken from its manifest. Pages
+/// that weren't downloaded are None, so the caller can apply the export's missing page policy.
+pub async fn get_chapter_pages(
+    pool: &SqlitePool,
+    chapter_id: i64,
+) -> Result<Vec<Option<PathBuf>>, AppError> {
+    let chapter_dir = get_chapter_dir(chapter_id);
+    let manifest = get_chapter_manifest(pool, chapter_id).await?;
+    if manifest.is_empty() {
+        return Err(eyre!("Chapter {} has no pages", chapter_id).into());
+    }
+    touch_cached_chapter(pool, chapter_id).await?;
+    Ok(manifest
+        .iter()
+        .map(|page| match page.status {
+            PageStatus::Downloaded => page.get_path(&chapter_dir),
+            _ => None,
+        })
+        .collect())
+}
+
+/// The language of a manga as a BCP 47 tag for the book metadata, taken from its source
+pub async fn get_manga_language(manga_id: i64) -> Result<String, AppError> {
+    let manga = get_manga_by_id(manga_id).await?;
+    Ok(book_language(
+        manga.source.as_ref().map(|source| source.lang.as_str()),
+    ))
+}
+
+/// Suwayomi reports pseudo languages such as `all` or `localsourcelang` for sources that
+/// aren't tied to one language, those fall back to English
+pub fn book_language(source_lang: Option<&str>) -> String {
+    match source_lang {
+        Some(lang)
+            if !matches!(lang, "all" | "multi" | "other")
+                && lang.split('-').next().is_some_and(|primary| {
+                    (2..=3).contains(&primary.len())
+                        && primary.chars().all(|c| c.is_ascii_alphabetic())
+                })
+                && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+        {
+            lang.to_string()
+        }
+        _ => "en".to_string(),
+    }
+}
+
+/// The pages an export is assembled from, the output of the image processing step
+pub fn get_export_chapter_pages(export_id: i64, chapter_id: i64) -> Result<Vec<PathBuf>, AppError> {
+    list_pages(&get_processed_chapter_dir(export_id, chapter_id))
+}
+
+/// Processed pages are named by their position, see `process_chapter`
+fn list_pages(dir: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut pages = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let page_number = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<i32>().ok())
+            .ok_or(eyre!("Unexpected page file {:?}", path))?;
+        pages.push((page_number, path));
+    }
+    pages.sort_by_key(|(page_number, _)| *page_number);
+    Ok(pages.into_iter().map(|(_, path)| path).collect())
+}
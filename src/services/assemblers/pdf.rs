@@ -0,0 +1,228 @@
+use std::{collections::HashSet, fs, io::Cursor, io::Write, sync::Arc};
+
+use eyre::eyre;
+use flate2::{write::ZlibEncoder, Compression};
+use image::{codecs::jpeg::JpegDecoder, ColorType, ImageDecoder, ImageFormat, ImageReader};
+use pdf_writer::{types::Direction, Content, Filter, Finish, Name, Pdf, Rect, Ref, TextStr};
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{
+        export::{get_export_base_dir, Export, ReadingDirection},
+        export_log::log_export_step,
+        export_part::ExportPart,
+    },
+    services::{control::check_interrupted, cover::get_cover_image, progress},
+    suwayomi::get_chapters_by_ids,
+    AppError,
+};
+
+use super::get_export_chapter_pages;
+
+/// Image data ready to be written as an image XObject
+struct PdfImage {
+    data: Vec<u8>,
+    filter: Filter,
+    grayscale: bool,
+    width: u32,
+    height: u32,
+}
+
+impl PdfImage {
+    /// JPEGs can be embedded as-is, everything else is decoded and deflated
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self, AppError> {
+        let reader = ImageReader::new(Cursor::new(&bytes)).with_guessed_format()?;
+        if reader.format() == Some(ImageFormat::Jpeg) {
+            let decoder = JpegDecoder::new(Cursor::new(&bytes))?;
+            let (width, height) = decoder.dimensions();
+            let grayscale = match decoder.color_type() {
+                ColorType::L8 => true,
+                ColorType::Rgb8 => false,
+                // Anything else can't go straight into a DCTDecode stream
+                _ => return Self::from_decoded(reader.decode()?),
+            };
+            return Ok(PdfImage {
+                data: bytes,
+                filter: Filter::DctDecode,
+                grayscale,
+                width,
+                height,
+            });
+        }
+        Self::from_decoded(reader.decode()?)
+    }
+
+    fn from_decoded(image: image::DynamicImage) -> Result<Self, AppError> {
+        let grayscale = !image.color().has_color();
+        let samples = if grayscale {
+            image.to_luma8().into_raw()
+        } else {
+            image.to_rgb8().into_raw()
+        };
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&samples)?;
+        Ok(PdfImage {
+            data: encoder.finish()?,
+            filter: Filter::FlateDecode,
+            grayscale,
+            width: image.width(),
+            height: image.height(),
+        })
+    }
+}
+
+struct PdfWriter {
+    pdf: Pdf,
+    next_ref: Ref,
+    page_tree_id: Ref,
+    page_ids: Vec<Ref>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        let mut next_ref = Ref::new(1);
+        let page_tree_id = next_ref.bump();
+        PdfWriter {
+            pdf: Pdf::new(),
+            next_ref,
+            page_tree_id,
+            page_ids: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> Ref {
+        self.next_ref.bump()
+    }
+
+    /// Adds a page sized to the image, with the image covering the whole page
+    fn add_image_page(&mut self, image: PdfImage) -> Ref {
+        let page_id = self.alloc();
+        let image_id = self.alloc();
+        let content_id = self.alloc();
+        let image_name = Name(b"Im1");
+        let (width, height) = (image.width as f32, image.height as f32);
+
+        let mut xobject = self.pdf.image_xobject(image_id, &image.data);
+        xobject.filter(image.filter);
+        xobject.width(image.width as i32);
+        xobject.height(image.height as i32);
+        if image.grayscale {
+            xobject.color_space().device_gray();
+        } else {
+            xobject.color_space().device_rgb();
+        }
+        xobject.bits_per_component(8);
+        xobject.finish();
+
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([width, 0.0, 0.0, height, 0.0, 0.0]);
+        content.x_object(image_name);
+        content.restore_state();
+        self.pdf.stream(content_id, &content.finish());
+
+        let mut page = self.pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, width, height));
+        page.parent(self.page_tree_id);
+        page.contents(content_id);
+        page.resources().x_objects().pair(image_name, image_id);
+        page.finish();
+
+        self.page_ids.push(page_id);
+        page_id
+    }
+}
+
+pub async fn assemble_pdf(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    part: &ExportPart,
+    chapter_ids: &HashSet<i64>,
+) -> Result<(), AppError> {
+    let chapters = match get_chapters_by_ids(chapter_ids).await? {
+        Some(chapters) => chapters.nodes,
+        None => return Err(eyre!("Chapters not found").into()),
+    };
+
+    let mut writer = PdfWriter::new();
+    let catalog_id = writer.alloc();
+    let outline_id = writer.alloc();
+    let info_id = writer.alloc();
+
+    if let Some(cover) = get_cover_image(export, &chapters).await? {
+        writer.add_image_page(PdfImage::from_bytes(cover.data)?);
+    }
+
+    // (title, first page of the chapter)
+    let mut bookmarks: Vec<(String, Ref)> = Vec::new();
+    for chapter in chapters.iter() {
+        let mut first_page = None;
+        for page in get_export_chapter_pages(export.id, chapter.id)? {
+            check_interrupted(export.id)?;
+            progress::advance(export.id, 1);
+            let page_id = writer.add_image_page(PdfImage::from_bytes(fs::read(&page)?)?);
+            first_page.get_or_insert(page_id);
+        }
+        if let Some(first_page) = first_page {
+            bookmarks.push((chapter.name.to_owned(), first_page));
+        }
+        log_export_step(
+            &pool,
+            export.id,
+            export.step,
+            format!("Added chapter {} to pdf", chapter.id).as_str(),
+        )
+        .await?;
+    }
+
+    let bookmark_ids: Vec<Ref> = bookmarks.iter().map(|_| writer.alloc()).collect();
+    for (index, (title, page_id)) in bookmarks.iter().enumerate() {
+        let mut item = writer.pdf.outline_item(bookmark_ids[index]);
+        item.title(TextStr(title));
+        item.parent(outline_id);
+        if index > 0 {
+            item.prev(bookmark_ids[index - 1]);
+        }
+        if let Some(next) = bookmark_ids.get(index + 1) {
+            item.next(*next);
+        }
+        item.dest().page(*page_id).fit();
+    }
+    let mut outline = writer.pdf.outline(outline_id);
+    if let (Some(first), Some(last)) = (bookmark_ids.first(), bookmark_ids.last()) {
+        outline.first(*first);
+        outline.last(*last);
+    }
+    outline.count(bookmark_ids.len() as i32);
+    outline.finish();
+
+    writer
+        .pdf
+        .pages(writer.page_tree_id)
+        .kids(writer.page_ids.iter().copied())
+        .count(writer.page_ids.len() as i32);
+
+    let mut catalog = writer.pdf.catalog(catalog_id);
+    catalog.pages(writer.page_tree_id);
+    catalog.outlines(outline_id);
+    if export.reading_direction == ReadingDirection::RightToLeft {
+        catalog.viewer_preferences().direction(Direction::R2L);
+    }
+    catalog.finish();
+
+    let mut info = writer.pdf.document_info(info_id);
+    info.title(TextStr(&part.title))
+        .author(TextStr(&export.author))
+        .creator(TextStr("manga-epub-browser"));
+    // The info dictionary has no series entries, so they go in as custom keys
+    if let Some(index) = part.series_index {
+        info.pair(Name(b"Series"), TextStr(&export.title));
+        info.pair(Name(b"SeriesIndex"), index as i32);
+    }
+    info.finish();
+
+    let pdf_base_dir = &get_export_base_dir();
+    std::fs::create_dir_all(pdf_base_dir)?;
+    fs::write(part.get_temp_path(), writer.pdf.finish())?;
+    Ok(())
+}
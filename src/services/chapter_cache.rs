@@ -0,0 +1,92 @@
+use std::{fs, sync::OnceLock};
+
+use sqlx::SqlitePool;
+use tokio::sync::Mutex;
+
+use crate::{
+    models::{
+        chapter_cache::{
+            delete_cached_chapter, get_chapter_cache_size, get_evictable_chapters,
+            get_evictable_manga_chapters,
+        },
+        chapter_page::delete_chapter_manifest,
+    },
+    util::env_or,
+    AppError,
+};
+
+use super::assemblers::get_chapter_dir;
+
+/// Most the chapter cache may hold, from `CHAPTER_CACHE_MAX_MB`. 0 or unset means no limit.
+pub fn get_chapter_cache_limit() -> Option<i64> {
+    match env_or("CHAPTER_CACHE_MAX_MB", 0i64) {
+        0 => None,
+        megabytes => Some(megabytes * 1024 * 1024),
+    }
+}
+
+/// Only one eviction runs at a time, they'd remove the same chapters otherwise
+fn eviction_lock() -> &'static Mutex<()> {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| Mutex::new(()))
+}
+
+/// Removes a chapter's pages from disk along with everything that's recorded about them
+pub async fn evict_chapter(pool: &SqlitePool, chapter_id: i64) -> Result<(), AppError> {
+    let chapter_dir = get_chapter_dir(chapter_id);
+    if chapter_dir.exists() {
+        fs::remove_dir_all(&chapter_dir)?;
+    }
+    delete_chapter_manifest(pool, chapter_id).await?;
+    delete_cached_chapter(pool, chapter_id).await?;
+    Ok(())
+}
+
+/// Evicts the least recently used chapters until the cache is within its limit. Chapters
+/// that exports still need are kept even if that leaves the cache over the limit.
+pub async fn enforce_chapter_cache_limit(pool: &SqlitePool) -> Result<(), AppError> {
+    let Some(limit) = get_chapter_cache_limit() else {
+        return Ok(());
+    };
+    let _lock = eviction_lock().lock().await;
+    let mut size = get_chapter_cache_size(pool).await?;
+    if size <= limit {
+        return Ok(());
+    }
+    let mut evicted = 0;
+    let mut freed = 0;
+    for (chapter_id, byte_size) in get_evictable_chapters(pool).await? {
+        if size <= limit {
+            break;
+        }
+        evict_chapter(pool, chapter_id).await?;
+        size -= byte_size;
+        evicted += 1;
+        freed += byte_size;
+    }
+    if evicted > 0 {
+        println!(
+            "Evicted {} chapters ({} MB) from the chapter cache",
+            evicted,
+            freed / 1024 / 1024
+        );
+    }
+    if size > limit {
+        println!("Chapter cache is over its limit, the rest is in use by exports");
+    }
+    Ok(())
+}
+
+/// Evicts a manga's chapters apart from the ones exports still need. Returns how many were
+/// evicted.
+pub async fn purge_manga_chapters(
+    pool: &SqlitePool,
+    manga_id: Option<i64>,
+) -> Result<usize, AppError> {
+    let _lock = eviction_lock().lock().await;
+    let chapters = get_evictable_manga_chapters(pool, manga_id).await?;
+    for chapter_id in &chapters {
+        evict_chapter(pool, *chapter_id).await?;
+    }
+    Ok(chapters.len())
+}
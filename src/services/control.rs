@@ -0,0 +1,110 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::AppError;
+
+/// Asks a running export to stop at the next page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interruption {
+    Pause,
+    Cancel,
+}
+
+impl fmt::Display for Interruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Interruption::Pause => write!(f, "Export paused"),
+            Interruption::Cancel => write!(f, "Export cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Interruption {}
+
+#[derive(Default)]
+struct Registry {
+    running: HashSet<i64>,
+    requested: HashMap<i64, Interruption>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Marks the export as having a task. Returns false when one is already running.
+pub fn start_running(export_id: i64) -> bool {
+    let mut registry = registry().lock().unwrap();
+    registry.requested.remove(&export_id);
+    registry.running.insert(export_id)
+}
+
+pub fn stop_running(export_id: i64) {
+    let mut registry = registry().lock().unwrap();
+    registry.running.remove(&export_id);
+    registry.requested.remove(&export_id);
+}
+
+pub fn is_running(export_id: i64) -> bool {
+    registry().lock().unwrap().running.contains(&export_id)
+}
+
+/// Either asks the export's task to stop or, when it has none, marks it as running so no
+/// worker can start it while the caller stops it. Returns true in the second case, and the
+/// caller has to [`stop_running`] once it's done.
+pub fn interrupt_or_claim(export_id: i64, interruption: Interruption) -> bool {
+    let mut registry = registry().lock().unwrap();
+    if registry.running.contains(&export_id) {
+        let requested = registry.requested.entry(export_id).or_insert(interruption);
+        // Cancelling wins over a pause that hasn't been picked up yet
+        if interruption == Interruption::Cancel {
+            *requested = Interruption::Cancel;
+        }
+        return false;
+    }
+    registry.running.insert(export_id)
+}
+
+/// Called between pages. Fails with an [`Interruption`] once a pause or cancel has been
+/// requested, so the export unwinds like it would on an error.
+pub fn check_interrupted(export_id: i64) -> Result<(), AppError> {
+    match registry().lock().unwrap().requested.get(&export_id) {
+        Some(interruption) => Err((*interruption).into()),
+        None => Ok(()),
+    }
+}
+
+/// Tells an interruption apart from a real failure
+pub fn get_interruption(error: &AppError) -> Option<Interruption> {
+    error.0.downcast_ref::<Interruption>().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupts_running_exports_and_claims_idle_ones() {
+        // Ids no other test uses, the registry is shared
+        let running = -1001;
+        assert!(start_running(running));
+        assert!(!interrupt_or_claim(running, Interruption::Pause));
+        assert!(!interrupt_or_claim(running, Interruption::Cancel));
+        assert!(!interrupt_or_claim(running, Interruption::Pause));
+        let error = check_interrupted(running).unwrap_err();
+        assert_eq!(get_interruption(&error), Some(Interruption::Cancel));
+        stop_running(running);
+
+        let idle = -1002;
+        assert!(interrupt_or_claim(idle, Interruption::Pause));
+        assert!(check_interrupted(idle).is_ok());
+        // Workers can't start the export while it's claimed
+        assert!(!start_running(idle));
+        stop_running(idle);
+        assert!(start_running(idle));
+        stop_running(idle);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{env, fs, path::Path};
+
+use eyre::eyre;
+
+use crate::{
+    models::export::{CoverSource, Export},
+    services::{assemblers::get_export_chapter_pages, processing::normalize_image},
+    suwayomi::{chapters_by_ids::ChaptersByIdsChaptersNodes, get_manga_by_id},
+    util::join_url,
+    AppError,
+};
+
+pub struct CoverImage {
+    pub data: Vec<u8>,
+    pub extension: String,
+}
+
+impl CoverImage {
+    pub fn mime_type(&self) -> String {
+        format!("image/{}", self.extension)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, AppError> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or(eyre!("Cover {:?} has no extension", path))?;
+        Ok(CoverImage {
+            data: fs::read(path)?,
+            extension: extension.to_string(),
+        })
+    }
+}
+
+/// Resolves the cover and converts it to a format the export supports, since uploads and
+/// thumbnails skip the page pipeline
+pub async fn get_cover_image(
+    export: &Export,
+    chapters: &[ChaptersByIdsChaptersNodes],
+) -> Result<Option<CoverImage>, AppError> {
+    let Some(cover) = find_cover_image(export, chapters).await? else {
+        return Ok(None);
+    };
+    let normalized = normalize_image(cover.data, export)?;
+    Ok(Some(CoverImage {
+        data: normalized.data,
+        extension: normalized.extension,
+    }))
+}
+
+/// Resolves the cover picked on the configure page. When that isn't available we fall back
+/// to the series thumbnail, then to the first page of the first chapter.
+async fn find_cover_image(
+    export: &Export,
+    chapters: &[ChaptersByIdsChaptersNodes],
+) -> Result<Option<CoverImage>, AppError> {
+    let chosen = match export.cover_source {
+        CoverSource::Upload => match &export.cover_path {
+            Some(path) if Path::new(path).is_file() => {
+                Some(CoverImage::from_file(Path::new(path))?)
+            }
+            _ => None,
+        },
+        CoverSource::Page => match (export.cover_chapter_id, export.cover_page) {
+            (Some(chapter_id), Some(page)) => get_page_cover(export.id, chapter_id, page)?,
+            _ => None,
+        },
+        CoverSource::Thumbnail => None,
+        CoverSource::FirstPage => match chapters.first() {
+            Some(chapter) => get_page_cover(export.id, chapter.id, 1)?,
+            None => None,
+        },
+    };
+    if chosen.is_some() {
+        return Ok(chosen);
+    }
+    if export.cover_source != CoverSource::Thumbnail {
+        println!(
+            "Cover for export {} not available from {}, falling back",
+            export.id, export.cover_source
+        );
+    }
+
+    if let Some(chapter) = chapters.first() {
+        match get_thumbnail_cover(chapter.manga_id).await {
+            Ok(Some(cover)) => return Ok(Some(cover)),
+            Ok(None) => {}
+            Err(e) => println!("Couldn't fetch thumbnail for export {}: {}", export.id, e),
+        }
+        return get_page_cover(export.id, chapter.id, 1);
+    }
+    Ok(None)
+}
+
+/// `page` is 1-based, the way it's entered on the configure page
+fn get_page_cover(
+    export_id: i64,
+    chapter_id: i64,
+    page: i64,
+) -> Result<Option<CoverImage>, AppError> {
+    let pages = match get_export_chapter_pages(export_id, chapter_id) {
+        Ok(pages) => pages,
+        Err(_) => return Ok(None),
+    };
+    match usize::try_from(page - 1)
+        .ok()
+        .and_then(|index| pages.get(index))
+    {
+        Some(path) => Ok(Some(CoverImage::from_file(path)?)),
+        None => Ok(None),
+    }
+}
+
+async fn get_thumbnail_cover(manga_id: i64) -> Result<Option<CoverImage>, AppError> {
+    let manga = get_manga_by_id(manga_id).await?;
+    let Some(thumbnail_url) = manga.thumbnail_url else {
+        return Ok(None);
+    };
+    let response = reqwest::get(join_url(&env::var("SUWAYOMI_URL")?, &thumbnail_url)?)
+        .await?
+        .error_for_status()?;
+    let content_type = match response.headers().get("Content-Type") {
+        Some(content_type) => content_type.to_str()?.to_string(),
+        None => return Ok(None),
+    };
+    let Some(extension) = content_type.strip_prefix("image/") else {
+        return Err(eyre!("Thumbnail is not an image: {:?}", content_type).into());
+    };
+    Ok(Some(CoverImage {
+        extension: extension.to_string(),
+        data: response.bytes().await?.to_vec(),
+    }))
+}
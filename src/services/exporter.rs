@@ -1,34 +1,88 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
 
 use eyre::eyre;
 use sqlx::SqlitePool;
+use tokio::{
+    sync::Notify,
+    time::{sleep, timeout, Duration},
+};
 
 use crate::{
     models::{
+        chapter_page::{get_chapter_manifest, PageStatus},
         export::{
-            get_export_by_id, get_export_chapters_by_id,
-            set_export_state, Export, ExportFormat, ExportState, ExportStep,
+            clear_chapter_downloads_queued, delete_export_by_id, get_dequeueable_chapter_downloads,
+            get_export_by_id, get_export_chapters_by_id, get_unreferenced_chapters,
+            set_export_failed, set_export_retrying, set_export_state, EpubLayout, Export,
+            ExportFormat, ExportState, ExportStep, MissingPagePolicy,
         },
-        export_log::log_export_step,
-    }, services::assemblers::cbz::assemble_cbz, suwayomi::{download_chapters_from_source, fetch_chapters_from_suwayomi}, AppError
+        export_job::{
+            claim_next_export_job, enqueue_export_job, remove_export_job,
+            requeue_started_export_jobs,
+        },
+        export_log::{log_export, log_export_step, LogLevel},
+        export_part::{get_export_parts, set_export_parts, ExportPart, NewExportPart},
+        missing_page::{get_skipped_chapters, set_missing_pages, NewMissingPage},
+    },
+    services::assemblers::cbz::assemble_cbz,
+    suwayomi::{
+        dequeue_chapter_downloads, download_chapters_from_source, fetch_chapters_from_suwayomi,
+        get_chapters_by_ids,
+    },
+    util::env_or,
+    AppError,
+};
+
+use super::assemblers::{
+    azw3::assemble_azw3, epub::assemble_epub, fixed_epub::assemble_fixed_layout_epub,
+    get_export_chapter_pages, kepub::assemble_kepub, pdf::assemble_pdf,
+};
+use super::chapter_cache::{enforce_chapter_cache_limit, evict_chapter};
+use super::control::{
+    check_interrupted, get_interruption, interrupt_or_claim, is_running, start_running,
+    stop_running, Interruption,
+};
+use super::progress::{self, finish_tracking, ProgressSaver};
+use super::{
+    processing::{get_processed_export_dir, process_export_images},
+    splitter::plan_export_parts,
 };
 
-use super::assemblers::epub::assemble_epub;
+/// How often idle workers look at the queue even without being woken
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(30);
 
-static STEPS: [ExportStep; 5] = [
+static STEPS: [ExportStep; 6] = [
     ExportStep::Begin,
     ExportStep::DownloadingFromSource,
     ExportStep::FetchingFromSuwayomi,
+    ExportStep::ProcessingImages,
     ExportStep::AssemblingFile,
     ExportStep::Complete,
 ];
 
-// TODO log stuff
+/// Runs the export's remaining steps, saving its progress as it goes
 async fn execute_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
-    let mut export = get_export_by_id(&*pool, id)
+    let saver = ProgressSaver::spawn(pool.clone(), id);
+    let result = run_export_steps(pool.clone(), id).await;
+    drop(saver);
+    finish_tracking(&pool, id).await?;
+    result
+}
+
+// TODO log stuff
+async fn run_export_steps(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
+    let mut export = get_export_by_id(&pool, id)
         .await?
         .ok_or(eyre!("Export not found"))?;
-    if export.state == ExportState::Completed {
+    if matches!(
+        export.state,
+        ExportState::Completed | ExportState::Paused | ExportState::Cancelled
+    ) {
         return Ok(());
     }
 
@@ -36,10 +90,28 @@ async fn execute_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError>
         .iter()
         .skip(STEPS.iter().position(|&s| s == export.step).unwrap_or(0))
     {
-        let _ = log_export_step(&*pool, export.id, export.step, "Starting step").await;
-        perform_export_step(pool.clone(), &mut export, *step).await?;
-        set_export_state(&*pool, id, &export.state, &export.step).await?;
-        let _ = log_export_step(&*pool, export.id, export.step, "Finished step").await;
+        let _ = log_export_step(&pool, export.id, export.step, "Starting step").await;
+        progress::start_step(id, export.state, *step, 0);
+        if let Err(e) = perform_export_step(pool.clone(), &mut export, *step).await {
+            if let Some(interruption) = get_interruption(&e) {
+                return handle_interruption(&pool, id, *step, interruption).await;
+            }
+            let message = e.to_string();
+            set_export_failed(&pool, id, *step, &message).await?;
+            progress::set_state(id, ExportState::Failed, *step);
+            let _ = log_export(
+                &pool,
+                id,
+                *step,
+                LogLevel::Error,
+                &format!("Failed: {}", message),
+            )
+            .await;
+            return Err(e);
+        }
+        set_export_state(&pool, id, &export.state, &export.step).await?;
+        progress::set_state(id, export.state, export.step);
+        let _ = log_export_step(&pool, export.id, export.step, "Finished step").await;
     }
     Ok(())
 }
@@ -49,36 +121,66 @@ async fn perform_export_step(
     export: &mut Export,
     step: ExportStep,
 ) -> Result<(), AppError> {
+    check_interrupted(export.id)?;
     match step {
         ExportStep::Begin => {
             export.state = ExportState::InProgress;
             export.step = ExportStep::DownloadingFromSource;
         }
         ExportStep::DownloadingFromSource => {
-            let ids = get_export_chapters_by_id(&*pool, export.id).await?;
-            download_chapters_from_source(&ids).await?;
+            let ids = get_export_chapters_by_id(&pool, export.id).await?;
+            download_chapters_from_source(&pool, export.id, &ids).await?;
             export.step = ExportStep::FetchingFromSuwayomi;
         }
         ExportStep::FetchingFromSuwayomi => {
-            let ids = get_export_chapters_by_id(&*pool, export.id).await?;
-            fetch_chapters_from_suwayomi(&ids).await?;
+            let ids = get_export_chapters_by_id(&pool, export.id).await?;
+            let failed = fetch_chapters_from_suwayomi(&pool, export.id, &ids).await?;
+            // The export's own chapters are safe from eviction while it's running
+            enforce_chapter_cache_limit(&pool).await?;
+            handle_missing_pages(&pool, export, &ids, &failed).await?;
+            export.step = ExportStep::ProcessingImages;
+        }
+        ExportStep::ProcessingImages => {
+            let ids = get_included_chapters(&pool, export.id).await?;
+            process_export_images(pool.clone(), export, &ids).await?;
             export.step = ExportStep::AssemblingFile;
         }
         ExportStep::AssemblingFile => {
-            let chapters = get_export_chapters_by_id(&*pool, export.id).await?;
-            match export.format {
-                ExportFormat::Epub => {
-                    assemble_epub(pool, export, &chapters).await?;
+            let ids = get_included_chapters(&pool, export.id).await?;
+            let chapters = match get_chapters_by_ids(&ids).await? {
+                Some(chapters) => chapters.nodes,
+                None => return Err(eyre!("Chapters not found").into()),
+            };
+            let planned = plan_export_parts(export, &chapters)?;
+            let new_parts: Vec<NewExportPart> =
+                planned.iter().map(|(part, _)| part.clone()).collect();
+            let parts = set_export_parts(&pool, export.id, &new_parts).await?;
+            let mut page_count = 0;
+            for (_, chapters) in planned.iter() {
+                for chapter_id in chapters {
+                    page_count += get_export_chapter_pages(export.id, *chapter_id)?.len();
                 }
-                ExportFormat::Cbz => {
-                    match assemble_cbz(pool, export, &chapters).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            dbg!(&e);
-                            return Err(e);
-                        }
-                    };
+            }
+            progress::add_total(export.id, page_count as i64);
+            for (part, (_, chapters)) in parts.iter().zip(planned.iter()) {
+                if parts.len() > 1 {
+                    log_export_step(
+                        &pool,
+                        export.id,
+                        export.step,
+                        &format!("Assembling part {} of {}", part.part, parts.len()),
+                    )
+                    .await?;
                 }
+                // Only complete files are moved into place
+                let temp_path = part.get_temp_path();
+                if let Err(e) = assemble_part(pool.clone(), export, part, chapters).await {
+                    if temp_path.exists() {
+                        fs::remove_file(&temp_path)?;
+                    }
+                    return Err(e);
+                }
+                fs::rename(&temp_path, part.get_path())?;
             }
             export.step = ExportStep::Complete;
         }
@@ -89,7 +191,161 @@ async fn perform_export_step(
     Ok(())
 }
 
-pub async fn resume_interrupted_exports(pool: Arc<SqlitePool>) -> Result<(), AppError> {
+/// Stops the export where it was. Paused exports pick up from the same step when resumed,
+/// cancelled ones are done for good. Only downloads the export queued itself are taken off
+/// Suwayomi's queue, and only when no other queued or running export needs them.
+async fn handle_interruption(
+    pool: &SqlitePool,
+    id: i64,
+    step: ExportStep,
+    interruption: Interruption,
+) -> Result<(), AppError> {
+    let chapter_ids = get_dequeueable_chapter_downloads(pool, id).await?;
+    if !chapter_ids.is_empty() {
+        if let Err(e) = dequeue_chapter_downloads(&chapter_ids).await {
+            println!("Couldn't dequeue chapters of export {}: {}", id, e);
+        }
+    }
+    clear_chapter_downloads_queued(pool, id).await?;
+    let state = match interruption {
+        Interruption::Pause => ExportState::Paused,
+        Interruption::Cancel => ExportState::Cancelled,
+    };
+    set_export_state(pool, id, &state, &step).await?;
+    progress::set_state(id, state, step);
+    remove_export_job(pool, id).await?;
+    log_export_step(pool, id, step, &interruption.to_string()).await?;
+    Ok(())
+}
+
+/// Applies the export's missing page policy to pages that are still missing after the
+/// fetch, logging each of them and keeping a list for the details page
+async fn handle_missing_pages(
+    pool: &SqlitePool,
+    export: &Export,
+    chapter_ids: &HashSet<i64>,
+    fetch_errors: &HashMap<i64, String>,
+) -> Result<(), AppError> {
+    let mut missing = Vec::new();
+    for chapter_id in chapter_ids {
+        let manifest = get_chapter_manifest(pool, *chapter_id).await?;
+        if manifest.is_empty() {
+            // Without a page list the whole chapter is missing
+            let error = fetch_errors
+                .get(chapter_id)
+                .cloned()
+                .unwrap_or("No pages".to_string());
+            log_export(
+                pool,
+                export.id,
+                export.step,
+                LogLevel::Warning,
+                &format!(
+                    "Chapter {} has no pages ({}): {}",
+                    chapter_id, export.missing_page_policy, error
+                ),
+            )
+            .await?;
+            missing.push(NewMissingPage {
+                chapter_id: *chapter_id,
+                page_index: None,
+                error: Some(error),
+            });
+            continue;
+        }
+        for page in manifest {
+            if page.status == PageStatus::Downloaded {
+                continue;
+            }
+            log_export(
+                pool,
+                export.id,
+                export.step,
+                LogLevel::Warning,
+                &format!(
+                    "Chapter {} page {} is missing ({}): {}",
+                    chapter_id,
+                    page.page_index + 1,
+                    export.missing_page_policy,
+                    page.error.as_deref().unwrap_or("not downloaded")
+                ),
+            )
+            .await?;
+            missing.push(NewMissingPage {
+                chapter_id: *chapter_id,
+                page_index: Some(page.page_index),
+                error: page.error,
+            });
+        }
+    }
+    set_missing_pages(pool, export.id, export.missing_page_policy, &missing).await?;
+
+    if !missing.is_empty() && export.missing_page_policy == MissingPagePolicy::Fail {
+        let chapters = missing
+            .iter()
+            .filter(|page| page.page_index.is_none())
+            .count();
+        return Err(eyre!(
+            "{} pages couldn't be downloaded and {} chapters have no pages",
+            missing.len() - chapters,
+            chapters
+        )
+        .into());
+    }
+    if get_included_chapters(pool, export.id).await?.is_empty() {
+        return Err(eyre!("Every chapter is missing pages").into());
+    }
+    Ok(())
+}
+
+/// The export's chapters minus the ones skipped for missing pages
+async fn get_included_chapters(
+    pool: &SqlitePool,
+    export_id: i64,
+) -> Result<HashSet<i64>, AppError> {
+    let skipped = get_skipped_chapters(pool, export_id).await?;
+    let mut ids = get_export_chapters_by_id(pool, export_id).await?;
+    ids.retain(|id| !skipped.contains(id));
+    Ok(ids)
+}
+
+async fn assemble_part(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    part: &ExportPart,
+    chapters: &HashSet<i64>,
+) -> Result<(), AppError> {
+    match export.format {
+        ExportFormat::Epub => match export.epub_layout {
+            EpubLayout::Reflowable => assemble_epub(pool, export, part, chapters).await?,
+            EpubLayout::Fixed => assemble_fixed_layout_epub(pool, export, part, chapters).await?,
+        },
+        ExportFormat::Cbz => {
+            match assemble_cbz(pool, export, part, chapters).await {
+                Ok(_) => {}
+                Err(e) => {
+                    dbg!(&e);
+                    return Err(e);
+                }
+            };
+        }
+        ExportFormat::Pdf => {
+            assemble_pdf(pool, export, part, chapters).await?;
+        }
+        ExportFormat::Kepub => {
+            assemble_kepub(pool, export, part, chapters).await?;
+        }
+        ExportFormat::Azw3 => {
+            assemble_azw3(pool, export, part, chapters).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts the worker pool. Jobs that were running when the server stopped are queued again,
+/// as are in-progress exports from before the queue existed.
+pub async fn start_export_workers(pool: Arc<SqlitePool>) -> Result<(), AppError> {
+    requeue_started_export_jobs(&pool).await?;
     let exports = sqlx::query!(
         r#"
         SELECT id FROM Export WHERE state = ?
@@ -98,21 +354,190 @@ pub async fn resume_interrupted_exports(pool: Arc<SqlitePool>) -> Result<(), App
     )
     .fetch_all(&*pool)
     .await?;
+    for export in exports {
+        println!("resuming export {}", export.id);
+        enqueue_export_job(&pool, export.id).await?;
+    }
 
-    if exports.is_empty() {
+    enforce_chapter_cache_limit(&pool).await?;
+
+    let workers = env_or("EXPORT_WORKERS", 2usize).max(1);
+    for _ in 0..workers {
+        tokio::spawn(run_export_worker(pool.clone()));
+    }
+    Ok(())
+}
+
+pub async fn begin_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
+    queue_export(&pool, id).await
+}
+
+/// Picks a failed export back up from the step that failed
+pub async fn retry_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
+    if !set_export_retrying(&pool, id).await? {
+        return Err(eyre!("Only failed exports can be retried").into());
+    }
+    let _ = log_export_step(&pool, id, ExportStep::Begin, "Retrying").await;
+    queue_export(&pool, id).await
+}
+
+/// Stops a running export at the next page. Exports without a task, e.g. ones that were
+/// in progress when the server stopped, are paused straight away.
+pub async fn pause_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
+    let export = get_export_by_id(&pool, id)
+        .await?
+        .ok_or(eyre!("Export not found"))?;
+    if export.state != ExportState::InProgress {
+        return Err(eyre!("Only running exports can be paused").into());
+    }
+    if !interrupt_or_claim(id, Interruption::Pause) {
         return Ok(());
     }
+    interrupt_claimed_export(&pool, id, Interruption::Pause).await
+}
 
-    for export in exports {
-        println!("resuming export {}", export.id);
-        let pool_clone = pool.clone();
-        tokio::spawn(async move { execute_export(pool_clone, export.id).await });
+pub async fn cancel_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
+    let export = get_export_by_id(&pool, id)
+        .await?
+        .ok_or(eyre!("Export not found"))?;
+    if !can_interrupt(export.state, Interruption::Cancel) {
+        return Err(eyre!("Export can't be cancelled").into());
     }
+    if !interrupt_or_claim(id, Interruption::Cancel) {
+        return Ok(());
+    }
+    interrupt_claimed_export(&pool, id, Interruption::Cancel).await
+}
 
+fn can_interrupt(state: ExportState, interruption: Interruption) -> bool {
+    match interruption {
+        Interruption::Pause => state == ExportState::InProgress,
+        Interruption::Cancel => matches!(
+            state,
+            ExportState::InProgress | ExportState::Paused | ExportState::Failed
+        ),
+    }
+}
+
+/// Stops an export that was claimed with [`interrupt_or_claim`]. A worker may have moved it
+/// on before the claim, so it's read again, and the claim is given up whatever happens.
+async fn interrupt_claimed_export(
+    pool: &SqlitePool,
+    id: i64,
+    interruption: Interruption,
+) -> Result<(), AppError> {
+    let result = match get_export_by_id(pool, id).await {
+        Ok(Some(export)) if can_interrupt(export.state, interruption) => {
+            handle_interruption(pool, id, export.step, interruption).await
+        }
+        Ok(Some(_)) => Err(eyre!("Export finished before it could be stopped").into()),
+        Ok(None) => Err(eyre!("Export not found").into()),
+        Err(e) => Err(e),
+    };
+    stop_running(id);
+    result
+}
+
+pub async fn resume_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
+    let export = get_export_by_id(&pool, id)
+        .await?
+        .ok_or(eyre!("Export not found"))?;
+    if export.state != ExportState::Paused {
+        return Err(eyre!("Only paused exports can be resumed").into());
+    }
+    set_export_state(&pool, id, &ExportState::InProgress, &export.step).await?;
+    progress::set_state(id, ExportState::InProgress, export.step);
+    let _ = log_export_step(&pool, id, export.step, "Resuming").await;
+    queue_export(&pool, id).await
+}
+
+/// Deletes the export along with its files. With `purge_chapters`, chapters that no other
+/// export includes are removed from the chapter cache too.
+pub async fn delete_export(
+    pool: Arc<SqlitePool>,
+    id: i64,
+    purge_chapters: bool,
+) -> Result<(), AppError> {
+    let export = get_export_by_id(&pool, id)
+        .await?
+        .ok_or(eyre!("Export not found"))?;
+    if is_running(id) {
+        return Err(eyre!("Pause or cancel the export before deleting it").into());
+    }
+    let chapter_ids = get_export_chapters_by_id(&pool, id).await?;
+    let parts = get_export_parts(&pool, id).await?;
+    delete_export_by_id(&pool, id).await?;
+
+    let mut files: Vec<PathBuf> = parts
+        .iter()
+        .flat_map(|part| [part.get_path(), part.get_temp_path()])
+        .collect();
+    if parts.is_empty() {
+        // Exports completed before parts were recorded
+        files.push(export.get_path());
+    }
+    if let Some(cover_path) = &export.cover_path {
+        files.push(cover_path.into());
+    }
+    for file in files {
+        if file.exists() {
+            fs::remove_file(&file)?;
+        }
+    }
+    let processed_dir = get_processed_export_dir(id);
+    if processed_dir.exists() {
+        fs::remove_dir_all(&processed_dir)?;
+    }
+
+    if purge_chapters {
+        for chapter_id in get_unreferenced_chapters(&pool, &chapter_ids).await? {
+            evict_chapter(&pool, chapter_id).await?;
+        }
+    }
+    println!("deleted export {}", id);
     Ok(())
 }
 
-pub async fn begin_export(pool: Arc<SqlitePool>, id: i64) -> Result<(), AppError> {
-    tokio::spawn(async move { execute_export(pool, id).await });
+/// Wakes a worker when a job is queued
+fn queue_notify() -> &'static Notify {
+    static NOTIFY: OnceLock<Notify> = OnceLock::new();
+    NOTIFY.get_or_init(Notify::new)
+}
+
+async fn queue_export(pool: &SqlitePool, id: i64) -> Result<(), AppError> {
+    enqueue_export_job(pool, id).await?;
+    queue_notify().notify_one();
     Ok(())
 }
+
+async fn run_export_worker(pool: Arc<SqlitePool>) {
+    loop {
+        match claim_next_export_job(&pool).await {
+            Ok(Some(id)) => run_export_job(pool.clone(), id).await,
+            // The timeout is a safety net in case a wakeup gets lost
+            Ok(None) => {
+                let _ = timeout(QUEUE_POLL_INTERVAL, queue_notify().notified()).await;
+            }
+            Err(e) => {
+                println!("Couldn't read the export queue: {}", e);
+                sleep(QUEUE_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn run_export_job(pool: Arc<SqlitePool>, id: i64) {
+    if start_running(id) {
+        if let Err(e) = execute_export(pool.clone(), id).await {
+            println!("Export {} failed: {}", id, e);
+        }
+        stop_running(id);
+    }
+    if let Err(e) = remove_export_job(&pool, id).await {
+        println!("Couldn't remove export {} from the queue: {}", id, e);
+    }
+    // Chapters the export held on to can go now
+    if let Err(e) = enforce_chapter_cache_limit(&pool).await {
+        println!("Couldn't evict from the chapter cache: {}", e);
+    }
+}
@@ -1,2 +1,8 @@
+mod assemblers;
+pub mod chapter_cache;
+pub mod control;
+mod cover;
 pub mod exporter;
-mod assemblers;
\ No newline at end of file
+mod processing;
+pub mod progress;
+mod splitter;
@@ -0,0 +1,173 @@
+use image::{DynamicImage, GrayImage};
+
+pub const DEFAULT_SAFETY_MARGIN: u32 = 8;
+pub const DEFAULT_MAX_PERCENT: u32 = 15;
+
+/// How far a pixel has to be from the border colour to count as content
+const CONTENT_THRESHOLD: u8 = 48;
+/// Rows and columns with fewer content pixels than this share are treated as noise
+const NOISE_RATIO: f64 = 0.005;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CropSettings {
+    pub safety_margin: u32,
+    pub max_percent: u32,
+}
+
+/// Crops uniform white or black borders. The content box is grown by the safety margin and
+/// no side loses more than `max_percent` of the page, so art running off the edge survives.
+pub fn crop_margins(image: &DynamicImage, settings: CropSettings) -> Option<DynamicImage> {
+    let gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let dark_background = border_mean(&gray) < 128.0;
+    let is_content = |value: u8| {
+        if dark_background {
+            value > CONTENT_THRESHOLD
+        } else {
+            value < 255 - CONTENT_THRESHOLD
+        }
+    };
+
+    let row_has_content = |y: u32| {
+        let count = (0..width)
+            .filter(|x| is_content(gray.get_pixel(*x, y).0[0]))
+            .count();
+        count as f64 > width as f64 * NOISE_RATIO
+    };
+    let column_has_content = |x: u32| {
+        let count = (0..height)
+            .filter(|y| is_content(gray.get_pixel(x, *y).0[0]))
+            .count();
+        count as f64 > height as f64 * NOISE_RATIO
+    };
+
+    let top = (0..height).find(|y| row_has_content(*y))?;
+    let bottom = (0..height).rev().find(|y| row_has_content(*y))? + 1;
+    let left = (0..width).find(|x| column_has_content(*x))?;
+    let right = (0..width).rev().find(|x| column_has_content(*x))? + 1;
+
+    let max_x = width * settings.max_percent.min(45) / 100;
+    let max_y = height * settings.max_percent.min(45) / 100;
+    let top = top.saturating_sub(settings.safety_margin).min(max_y);
+    let left = left.saturating_sub(settings.safety_margin).min(max_x);
+    let bottom = (bottom + settings.safety_margin)
+        .min(height)
+        .max(height - max_y);
+    let right = (right + settings.safety_margin)
+        .min(width)
+        .max(width - max_x);
+
+    if top == 0 && left == 0 && bottom == height && right == width {
+        return None;
+    }
+    Some(image.crop_imm(left, top, right - left, bottom - top))
+}
+
+fn border_mean(image: &GrayImage) -> f64 {
+    let (width, height) = image.dimensions();
+    let mut sum = 0u64;
+    let mut count = 0u64;
+    for x in 0..width {
+        sum += image.get_pixel(x, 0).0[0] as u64 + image.get_pixel(x, height - 1).0[0] as u64;
+        count += 2;
+    }
+    for y in 0..height {
+        sum += image.get_pixel(0, y).0[0] as u64 + image.get_pixel(width - 1, y).0[0] as u64;
+        count += 2;
+    }
+    sum as f64 / count as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Luma};
+
+    use super::*;
+
+    const SETTINGS: CropSettings = CropSettings {
+        safety_margin: 0,
+        max_percent: 45,
+    };
+
+    /// A `background` page of 200x300 with a content box drawn in the other colour
+    fn page(background: u8, content: (u32, u32, u32, u32)) -> DynamicImage {
+        let (left, top, right, bottom) = content;
+        DynamicImage::ImageLuma8(GrayImage::from_fn(200, 300, |x, y| {
+            if (left..right).contains(&x) && (top..bottom).contains(&y) {
+                Luma([255 - background])
+            } else {
+                Luma([background])
+            }
+        }))
+    }
+
+    fn bounds(image: &DynamicImage, settings: CropSettings) -> Option<(u32, u32)> {
+        crop_margins(image, settings).map(|cropped| cropped.dimensions())
+    }
+
+    #[test]
+    fn crops_white_borders() {
+        let image = page(255, (20, 30, 180, 270));
+        assert_eq!(bounds(&image, SETTINGS), Some((160, 240)));
+    }
+
+    #[test]
+    fn crops_black_borders() {
+        let image = page(0, (10, 15, 190, 285));
+        assert_eq!(bounds(&image, SETTINGS), Some((180, 270)));
+    }
+
+    #[test]
+    fn keeps_pages_without_borders() {
+        let image = page(255, (0, 0, 200, 300));
+        assert!(crop_margins(&image, SETTINGS).is_none());
+    }
+
+    #[test]
+    fn ignores_specks_in_the_border() {
+        let mut gray = page(255, (20, 30, 180, 270)).to_luma8();
+        gray.put_pixel(2, 2, Luma([0]));
+        let image = DynamicImage::ImageLuma8(gray);
+        assert_eq!(bounds(&image, SETTINGS), Some((160, 240)));
+    }
+
+    #[test]
+    fn grows_the_box_by_the_safety_margin() {
+        let image = page(255, (20, 30, 180, 270));
+        let settings = CropSettings {
+            safety_margin: 8,
+            ..SETTINGS
+        };
+        assert_eq!(bounds(&image, settings), Some((176, 256)));
+
+        // The margin never reaches past the page
+        let settings = CropSettings {
+            safety_margin: 50,
+            ..SETTINGS
+        };
+        assert!(crop_margins(&image, settings).is_none());
+    }
+
+    #[test]
+    fn caps_the_crop_per_side() {
+        let image = page(255, (60, 90, 140, 210));
+        let settings = CropSettings {
+            max_percent: 10,
+            ..SETTINGS
+        };
+        // 10% of 200 and 300 at most from each side
+        assert_eq!(bounds(&image, settings), Some((160, 240)));
+
+        // Anything past 45% is treated as 45%
+        let settings = CropSettings {
+            max_percent: 100,
+            ..SETTINGS
+        };
+        assert_eq!(bounds(&image, settings), Some((80, 120)));
+        let image = page(255, (95, 140, 105, 160));
+        assert_eq!(bounds(&image, settings), Some((20, 30)));
+    }
+}
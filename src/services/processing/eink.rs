@@ -0,0 +1,124 @@
+use image::{DynamicImage, GrayImage, Luma, RgbaImage};
+
+/// Gray levels most e-ink panels can actually show
+const LEVELS: u8 = 16;
+
+/// Converts to grayscale, then applies the gamma curve and optionally dithers down to
+/// [`LEVELS`] shades so the device doesn't have to
+pub fn to_eink(image: &DynamicImage, gamma: Option<f64>, dither: bool) -> DynamicImage {
+    let mut gray = image.to_luma8();
+    if let Some(gamma) = gamma {
+        apply_gamma(&mut gray, gamma);
+    }
+    if dither {
+        floyd_steinberg(&mut gray);
+    }
+    DynamicImage::ImageLuma8(gray)
+}
+
+/// The gamma curve on its own for color pages, applied to each color channel
+pub fn apply_color_gamma(image: &DynamicImage, gamma: f64) -> DynamicImage {
+    let table = gamma_table(gamma);
+    let mut rgba: RgbaImage = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = table[*channel as usize];
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// `gamma` above 1 darkens midtones, below 1 lightens them. Black and white stay put.
+fn apply_gamma(image: &mut GrayImage, gamma: f64) {
+    let table = gamma_table(gamma);
+    for pixel in image.pixels_mut() {
+        pixel.0[0] = table[pixel.0[0] as usize];
+    }
+}
+
+fn gamma_table(gamma: f64) -> Vec<u8> {
+    (0..=255u8)
+        .map(|value| ((value as f64 / 255.0).powf(gamma) * 255.0).round() as u8)
+        .collect()
+}
+
+/// Error diffusion to evenly spaced gray levels, pushing the rounding error onto the
+/// neighbouring pixels that haven't been visited yet
+fn floyd_steinberg(image: &mut GrayImage) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let step = 255.0 / (LEVELS - 1) as f32;
+    let mut values: Vec<f32> = image.pixels().map(|pixel| pixel.0[0] as f32).collect();
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let old = values[index].clamp(0.0, 255.0);
+            let new = (old / step).round() * step;
+            values[index] = new;
+            let error = old - new;
+            if x + 1 < width {
+                values[index + 1] += error * 7.0 / 16.0;
+            }
+            if y + 1 < height {
+                if x > 0 {
+                    values[index + width - 1] += error * 3.0 / 16.0;
+                }
+                values[index + width] += error * 5.0 / 16.0;
+                if x + 1 < width {
+                    values[index + width + 1] += error / 16.0;
+                }
+            }
+        }
+    }
+
+    for (pixel, value) in image.pixels_mut().zip(values) {
+        *pixel = Luma([value.round() as u8]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([((x * 7 + y * 3) % 256) as u8]))
+    }
+
+    #[test]
+    fn gamma_one_is_the_identity() {
+        let table = gamma_table(1.0);
+        assert!(table.iter().enumerate().all(|(i, value)| *value == i as u8));
+    }
+
+    #[test]
+    fn gamma_keeps_black_and_white() {
+        for gamma in [0.5, 1.8, 2.2] {
+            let table = gamma_table(gamma);
+            assert_eq!((table[0], table[255]), (0, 255));
+            assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+        assert!(gamma_table(1.8)[128] < 128);
+        assert!(gamma_table(0.5)[128] > 128);
+    }
+
+    #[test]
+    fn dithering_only_uses_the_allowed_levels() {
+        let mut image = gradient(64, 64);
+        floyd_steinberg(&mut image);
+        let step = 255 / (LEVELS - 1);
+        assert!(image.pixels().all(|pixel| pixel.0[0] % step == 0));
+
+        let levels: std::collections::HashSet<u8> =
+            image.pixels().map(|pixel| pixel.0[0]).collect();
+        assert!(levels.len() <= LEVELS as usize);
+        assert!(levels.contains(&0) && levels.contains(&255));
+    }
+
+    #[test]
+    fn dithering_keeps_the_average_brightness() {
+        let mut image = GrayImage::from_pixel(32, 32, Luma([100]));
+        floyd_steinberg(&mut image);
+        let average = image.pixels().map(|pixel| pixel.0[0] as f64).sum::<f64>() / (32.0 * 32.0);
+        assert!((average - 100.0).abs() < 2.0, "average {}", average);
+    }
+}
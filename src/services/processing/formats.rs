@@ -0,0 +1,117 @@
+use image::ImageFormat;
+
+use crate::models::export::{CbzPageFormat, ExportFormat};
+
+/// Image formats every reader of the export format can open. E-readers only reliably
+/// handle JPEG and PNG inside EPUB, KEPUB and AZW3, comic readers are more forgiving.
+pub fn is_supported(export_format: ExportFormat, format: ImageFormat) -> bool {
+    match export_format {
+        ExportFormat::Cbz => matches!(
+            format,
+            ImageFormat::Jpeg
+                | ImageFormat::Png
+                | ImageFormat::Gif
+                | ImageFormat::WebP
+                | ImageFormat::Avif
+        ),
+        _ => matches!(format, ImageFormat::Jpeg | ImageFormat::Png),
+    }
+}
+
+/// Lossy sources stay lossy so pages don't balloon in size, unless the result was dithered
+/// since compression artifacts would undo that. CBZ pages can all go to AVIF, or to WebP
+/// where they'd otherwise be PNG.
+pub fn output_format(
+    export_format: ExportFormat,
+    source: Option<ImageFormat>,
+    dither: bool,
+    cbz_page_format: CbzPageFormat,
+) -> ImageFormat {
+    let cbz = export_format == ExportFormat::Cbz;
+    let lossy = matches!(
+        source,
+        Some(ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Avif)
+    );
+    if cbz && cbz_page_format == CbzPageFormat::Avif && !dither {
+        ImageFormat::Avif
+    } else if lossy && !dither {
+        ImageFormat::Jpeg
+    } else if cbz && cbz_page_format == CbzPageFormat::Webp {
+        ImageFormat::WebP
+    } else {
+        ImageFormat::Png
+    }
+}
+
+/// Whether a page already in `format` has to be re-encoded to match the CBZ page format
+pub fn needs_cbz_transcode(cbz_page_format: CbzPageFormat, format: ImageFormat) -> bool {
+    match cbz_page_format {
+        CbzPageFormat::Original => false,
+        CbzPageFormat::Webp => format == ImageFormat::Png,
+        CbzPageFormat::Avif => format != ImageFormat::Avif,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lossy_pages_stay_lossy() {
+        for format in [ImageFormat::Jpeg, ImageFormat::WebP, ImageFormat::Avif] {
+            assert_eq!(
+                output_format(ExportFormat::Epub, Some(format), false, CbzPageFormat::Avif),
+                ImageFormat::Jpeg
+            );
+        }
+        assert_eq!(
+            output_format(
+                ExportFormat::Epub,
+                Some(ImageFormat::Png),
+                false,
+                CbzPageFormat::Avif
+            ),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn cbz_page_formats() {
+        let cbz = |source, dither, page_format| {
+            output_format(ExportFormat::Cbz, Some(source), dither, page_format)
+        };
+        assert_eq!(
+            cbz(ImageFormat::Png, false, CbzPageFormat::Original),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            cbz(ImageFormat::Png, false, CbzPageFormat::Webp),
+            ImageFormat::WebP
+        );
+        assert_eq!(
+            cbz(ImageFormat::Jpeg, false, CbzPageFormat::Webp),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(
+            cbz(ImageFormat::Jpeg, false, CbzPageFormat::Avif),
+            ImageFormat::Avif
+        );
+        // Dithered pages have to stay lossless
+        assert_eq!(
+            cbz(ImageFormat::Jpeg, true, CbzPageFormat::Avif),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn avif_is_only_kept_in_cbz() {
+        assert!(is_supported(ExportFormat::Cbz, ImageFormat::Avif));
+        assert!(!is_supported(ExportFormat::Epub, ImageFormat::Avif));
+        assert!(needs_cbz_transcode(CbzPageFormat::Avif, ImageFormat::Jpeg));
+        assert!(!needs_cbz_transcode(CbzPageFormat::Avif, ImageFormat::Avif));
+        assert!(!needs_cbz_transcode(
+            CbzPageFormat::Original,
+            ImageFormat::Avif
+        ));
+    }
+}
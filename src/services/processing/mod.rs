@@ -0,0 +1,490 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::eyre;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat, ImageReader, Luma};
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{
+        export::{
+            set_export_crop_stats, CbzPageFormat, Export, ExportFormat, MissingPagePolicy,
+            ReadingDirection, SpreadMode,
+        },
+        export_log::log_export_step,
+        missing_page::get_placeholder_chapters,
+    },
+    services::{assemblers::get_chapter_pages, control::check_interrupted, progress},
+    util::image_extension,
+    AppError,
+};
+
+mod crop;
+mod eink;
+mod formats;
+mod resize;
+mod spreads;
+mod webtoon;
+
+use crop::CropSettings;
+
+/// Size of placeholder pages when the export has no target device
+const PLACEHOLDER_SIZE: (u32, u32) = (1200, 1600);
+const PLACEHOLDER_LINE_WIDTH: u32 = 4;
+
+/// Everything the image stage needs from the export, so chapters can be processed on the
+/// blocking thread pool
+#[derive(Debug, Clone)]
+pub struct ImageSettings {
+    pub target_resolution: Option<(u32, u32)>,
+    pub grayscale: bool,
+    pub gamma: Option<f64>,
+    pub dither: bool,
+    pub spread_mode: SpreadMode,
+    pub reading_direction: ReadingDirection,
+    pub crop: Option<CropSettings>,
+    pub export_format: ExportFormat,
+    pub cbz_page_format: CbzPageFormat,
+    pub webtoon: bool,
+    pub placeholders: bool,
+}
+
+impl ImageSettings {
+    pub fn from_export(export: &Export) -> Self {
+        ImageSettings {
+            target_resolution: export.get_target_resolution(),
+            // dithering only makes sense on gray levels
+            grayscale: export.grayscale || export.dither,
+            // 1 leaves every value where it is
+            gamma: export.gamma.filter(|gamma| *gamma > 0.0 && *gamma != 1.0),
+            dither: export.dither,
+            // Short slices at the end of a strip would look like spreads
+            spread_mode: if export.webtoon {
+                SpreadMode::Keep
+            } else {
+                export.spread_mode
+            },
+            reading_direction: export.reading_direction,
+            crop: export.crop_margins.then(|| CropSettings {
+                safety_margin: export
+                    .crop_safety_margin
+                    .map_or(crop::DEFAULT_SAFETY_MARGIN, |margin| margin.max(0) as u32),
+                max_percent: export
+                    .crop_max_percent
+                    .map_or(crop::DEFAULT_MAX_PERCENT, |percent| percent.max(0) as u32),
+            }),
+            export_format: export.format,
+            cbz_page_format: export.cbz_page_format,
+            webtoon: export.webtoon,
+            placeholders: export.missing_page_policy == MissingPagePolicy::Placeholder,
+        }
+    }
+
+    /// Without transforms pages are only decoded when their format has to change
+    pub fn has_transforms(&self) -> bool {
+        self.target_resolution.is_some()
+            || self.grayscale
+            || self.gamma.is_some()
+            || self.spread_mode != SpreadMode::Keep
+            || self.crop.is_some()
+            || self.webtoon
+    }
+
+    /// Pages already in a supported format are kept as is, unless CBZ pages should be WebP
+    /// or AVIF
+    fn needs_transcode(&self, format: Option<ImageFormat>) -> bool {
+        match format {
+            Some(format) => {
+                !formats::is_supported(self.export_format, format)
+                    || (self.export_format == ExportFormat::Cbz
+                        && formats::needs_cbz_transcode(self.cbz_page_format, format))
+            }
+            None => true,
+        }
+    }
+}
+
+/// Totals for the details page, pixel counts are from before and after cropping
+#[derive(Debug, Default)]
+struct ProcessingStats {
+    pages: usize,
+    pixels_total: u64,
+    pixels_removed: u64,
+}
+
+pub struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub extension: String,
+}
+
+pub fn get_processed_export_dir(export_id: i64) -> PathBuf {
+    let processed_base_dir = env::var("PROCESSED_PATH").unwrap_or("data/processed".to_string());
+    Path::new(&processed_base_dir).join(export_id.to_string())
+}
+
+pub fn get_processed_chapter_dir(export_id: i64, chapter_id: i64) -> PathBuf {
+    get_processed_export_dir(export_id).join(chapter_id.to_string())
+}
+
+/// Runs every page of the export through the image stage. The output is kept per export,
+/// apart from the raw chapter cache, since it depends on the export's settings.
+pub async fn process_export_images(
+    pool: Arc<SqlitePool>,
+    export: &Export,
+    chapter_ids: &HashSet<i64>,
+) -> Result<(), AppError> {
+    // Start over so a retry never mixes output from different settings
+    let export_dir = get_processed_export_dir(export.id);
+    if export_dir.exists() {
+        fs::remove_dir_all(&export_dir)?;
+    }
+    let settings = ImageSettings::from_export(export);
+    let mut stats = ProcessingStats::default();
+
+    // A chapter without a page list is one missing page, which becomes a single placeholder
+    let placeholder_chapters = get_placeholder_chapters(&pool, export.id).await?;
+    let mut chapters = Vec::new();
+    for chapter_id in chapter_ids {
+        let pages = if placeholder_chapters.contains(chapter_id) {
+            vec![None]
+        } else {
+            get_chapter_pages(&pool, *chapter_id).await?
+        };
+        chapters.push((chapter_id, pages));
+    }
+    let page_count: usize = chapters.iter().map(|(_, pages)| pages.len()).sum();
+    progress::add_total(export.id, page_count as i64);
+
+    for (chapter_id, pages) in chapters {
+        let output_dir = get_processed_chapter_dir(export.id, *chapter_id);
+        let chapter_settings = settings.clone();
+        let export_id = export.id;
+        let chapter_stats = tokio::task::spawn_blocking(move || {
+            process_chapter(export_id, &chapter_settings, &pages, &output_dir)
+        })
+        .await??;
+        log_export_step(
+            &pool,
+            export.id,
+            export.step,
+            &format!(
+                "Processed chapter {} into {} pages",
+                chapter_id, chapter_stats.pages
+            ),
+        )
+        .await?;
+        stats.pages += chapter_stats.pages;
+        stats.pixels_total += chapter_stats.pixels_total;
+        stats.pixels_removed += chapter_stats.pixels_removed;
+    }
+    set_export_crop_stats(
+        &pool,
+        export.id,
+        stats.pixels_total as i64,
+        stats.pixels_removed as i64,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Writes the processed pages numbered from 0 in reading order. The assemblers name and
+/// order pages by these numbers, so split spreads stay in place.
+fn process_chapter(
+    export_id: i64,
+    settings: &ImageSettings,
+    pages: &[Option<PathBuf>],
+    output_dir: &Path,
+) -> Result<ProcessingStats, AppError> {
+    fs::create_dir_all(output_dir)?;
+    let mut stats = ProcessingStats::default();
+    if settings.webtoon {
+        process_strip(export_id, settings, pages, output_dir, &mut stats)?;
+        return Ok(stats);
+    }
+    for page in pages {
+        check_interrupted(export_id)?;
+        process_source_page(settings, page.as_deref(), output_dir, &mut stats)?;
+        progress::advance(export_id, 1);
+    }
+    Ok(stats)
+}
+
+/// Writes the output for one source page, usually one page but split spreads give two
+fn process_source_page(
+    settings: &ImageSettings,
+    page: Option<&Path>,
+    output_dir: &Path,
+    stats: &mut ProcessingStats,
+) -> Result<(), AppError> {
+    let Some(page) = page else {
+        return write_page(output_dir, stats, placeholder_page(settings)?);
+    };
+    let data = fs::read(page)?;
+    let format = image::guess_format(&data).ok();
+    if !settings.has_transforms() && !settings.needs_transcode(format) {
+        // Link rather than copy. Re-fetched raw pages are renamed over the old file instead of
+        // being rewritten, so the link keeps the page the export started with.
+        let output = output_dir.join(format!("{}.{}", stats.pages, extension_for(format)));
+        if fs::hard_link(page, &output).is_err() {
+            fs::write(&output, &data)?;
+        }
+        stats.pages += 1;
+        return Ok(());
+    }
+    for image in process_page(settings, data, stats)? {
+        write_page(output_dir, stats, image)?;
+    }
+    Ok(())
+}
+
+/// Webtoon chapters are stitched into one strip and re-sliced, so the output pages don't
+/// line up with the source images
+fn process_strip(
+    export_id: i64,
+    settings: &ImageSettings,
+    pages: &[Option<PathBuf>],
+    output_dir: &Path,
+    stats: &mut ProcessingStats,
+) -> Result<(), AppError> {
+    let mut slicer = webtoon::StripSlicer::new(webtoon::page_ratio(settings.target_resolution));
+    let mut format = None;
+    for page in pages {
+        check_interrupted(export_id)?;
+        if let Some(page) = page {
+            let (page_format, image) = decode_image(&fs::read(page)?)?;
+            format = page_format;
+            for slice in slicer.push(&image) {
+                for image in process_image(settings, slice, format, None, stats)? {
+                    write_page(output_dir, stats, image)?;
+                }
+            }
+        } else {
+            // The placeholder gets a page of its own instead of being stitched in
+            for slice in slicer.finish() {
+                for image in process_image(settings, slice, format, None, stats)? {
+                    write_page(output_dir, stats, image)?;
+                }
+            }
+            write_page(output_dir, stats, placeholder_page(settings)?)?;
+        }
+        progress::advance(export_id, 1);
+    }
+    for slice in slicer.finish() {
+        for image in process_image(settings, slice, format, None, stats)? {
+            write_page(output_dir, stats, image)?;
+        }
+    }
+    Ok(())
+}
+
+/// Stands in for a page that couldn't be downloaded: a gray page that's crossed out
+fn placeholder_page(settings: &ImageSettings) -> Result<ProcessedImage, AppError> {
+    if !settings.placeholders {
+        return Err(eyre!("A page is missing and the export doesn't allow placeholders").into());
+    }
+    let (width, height) = settings.target_resolution.unwrap_or(PLACEHOLDER_SIZE);
+    let mut page = GrayImage::from_pixel(width, height, Luma([224]));
+    for y in 0..height {
+        let x = (y as u64 * width as u64 / height as u64) as u32;
+        for offset in 0..PLACEHOLDER_LINE_WIDTH {
+            for x in [x + offset, (width - 1).saturating_sub(x + offset)] {
+                if x < width {
+                    page.put_pixel(x, y, Luma([128]));
+                }
+            }
+        }
+    }
+    encode_image(
+        &DynamicImage::ImageLuma8(page),
+        formats::output_format(
+            settings.export_format,
+            None,
+            false,
+            settings.cbz_page_format,
+        ),
+    )
+}
+
+fn write_page(
+    output_dir: &Path,
+    stats: &mut ProcessingStats,
+    image: ProcessedImage,
+) -> Result<(), AppError> {
+    fs::write(
+        output_dir.join(format!("{}.{}", stats.pages, image.extension)),
+        image.data,
+    )?;
+    stats.pages += 1;
+    Ok(())
+}
+
+/// A page can come out as several images, e.g. when a spread gets split
+fn process_page(
+    settings: &ImageSettings,
+    data: Vec<u8>,
+    stats: &mut ProcessingStats,
+) -> Result<Vec<ProcessedImage>, AppError> {
+    let (format, source) = decode_image(&data)?;
+    process_image(settings, source, format, Some(&data), stats)
+}
+
+/// `original` is the encoded source, which is reused when the image comes through unchanged
+fn process_image(
+    settings: &ImageSettings,
+    mut source: DynamicImage,
+    format: Option<ImageFormat>,
+    original: Option<&[u8]>,
+    stats: &mut ProcessingStats,
+) -> Result<Vec<ProcessedImage>, AppError> {
+    let source_dimensions = source.dimensions();
+
+    let source_pixels = source.width() as u64 * source.height() as u64;
+    stats.pixels_total += source_pixels;
+    if let Some(crop) = settings.crop {
+        if let Some(cropped) = crop::crop_margins(&source, crop) {
+            stats.pixels_removed +=
+                source_pixels - cropped.width() as u64 * cropped.height() as u64;
+            source = cropped;
+        }
+    }
+
+    let images = spreads::handle_spread(&source, settings.spread_mode, settings.reading_direction)
+        .unwrap_or_else(|| vec![source]);
+    let mut processed = Vec::with_capacity(images.len());
+    for mut image in images {
+        let mut changed = image.dimensions() != source_dimensions;
+        if let Some((width, height)) = settings.target_resolution {
+            if let Some(resized) = resize::fit_within(&image, width, height) {
+                image = resized;
+                changed = true;
+            }
+        }
+        if settings.grayscale {
+            image = eink::to_eink(&image, settings.gamma, settings.dither);
+            changed = true;
+        } else if let Some(gamma) = settings.gamma {
+            image = eink::apply_color_gamma(&image, gamma);
+            changed = true;
+        }
+
+        if let Some(original) = original.filter(|_| !changed && !settings.needs_transcode(format)) {
+            // Untouched pages keep their original bytes instead of being re-encoded
+            processed.push(ProcessedImage {
+                extension: extension_for(format).to_string(),
+                data: original.to_vec(),
+            });
+            continue;
+        }
+        let output_format = formats::output_format(
+            settings.export_format,
+            format,
+            settings.dither,
+            settings.cbz_page_format,
+        );
+        processed.push(encode_image(&image, output_format)?);
+    }
+    Ok(processed)
+}
+
+/// Brings an image that doesn't go through the page pipeline, like the cover, into a
+/// format the export supports
+pub fn normalize_image(data: Vec<u8>, export: &Export) -> Result<ProcessedImage, AppError> {
+    let settings = ImageSettings::from_export(export);
+    let format = image::guess_format(&data).ok();
+    if !settings.needs_transcode(format) {
+        return Ok(ProcessedImage {
+            extension: extension_for(format).to_string(),
+            data,
+        });
+    }
+    let (format, image) = decode_image(&data)?;
+    encode_image(
+        &image,
+        formats::output_format(
+            settings.export_format,
+            format,
+            false,
+            settings.cbz_page_format,
+        ),
+    )
+}
+
+fn decode_image(data: &[u8]) -> Result<(Option<ImageFormat>, DynamicImage), AppError> {
+    let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let format = reader.format();
+    if format == Some(ImageFormat::Avif) && !cfg!(feature = "avif-decoder") {
+        return Err(
+            eyre!("AVIF pages can't be decoded, build with the avif-decoder feature").into(),
+        );
+    }
+    Ok((format, reader.decode()?))
+}
+
+fn encode_image(image: &DynamicImage, format: ImageFormat) -> Result<ProcessedImage, AppError> {
+    let mut data = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, 90);
+            match image {
+                DynamicImage::ImageLuma8(gray) => gray.write_with_encoder(encoder)?,
+                _ => image.to_rgb8().write_with_encoder(encoder)?,
+            }
+        }
+        // The WebP encoder is lossless only and doesn't take 16-bit images
+        ImageFormat::WebP => match image {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_) => image.write_to(&mut Cursor::new(&mut data), format)?,
+            _ => DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_to(&mut Cursor::new(&mut data), format)?,
+        },
+        // The default speed takes seconds per page
+        ImageFormat::Avif => {
+            let encoder =
+                image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut data, 8, 80);
+            match image {
+                DynamicImage::ImageLuma8(_)
+                | DynamicImage::ImageRgb8(_)
+                | DynamicImage::ImageRgba8(_) => image.write_with_encoder(encoder)?,
+                _ => DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder)?,
+            }
+        }
+        _ => image.write_to(&mut Cursor::new(&mut data), format)?,
+    }
+    Ok(ProcessedImage {
+        data,
+        extension: extension_for(Some(format)).to_string(),
+    })
+}
+
+fn extension_for(format: Option<ImageFormat>) -> &'static str {
+    format.and_then(image_extension).unwrap_or("bin")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn encodes_avif() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, 128])
+        }));
+        let processed = encode_image(&image, ImageFormat::Avif).unwrap();
+        assert_eq!(processed.extension, "avif");
+        assert_eq!(
+            image::guess_format(&processed.data).unwrap(),
+            ImageFormat::Avif
+        );
+
+        let gray = DynamicImage::ImageLuma8(image.to_luma8());
+        assert!(encode_image(&gray, ImageFormat::Avif).is_ok());
+    }
+}
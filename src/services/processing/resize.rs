@@ -0,0 +1,33 @@
+use image::{imageops::FilterType, DynamicImage};
+
+/// Scales the image down to fit inside `width` x `height`, keeping its aspect ratio.
+/// Images that already fit are left alone, there's nothing to gain from upscaling.
+pub fn fit_within(image: &DynamicImage, width: u32, height: u32) -> Option<DynamicImage> {
+    if image.width() <= width && image.height() <= height {
+        return None;
+    }
+    Some(image.resize(width, height, FilterType::Lanczos3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_images_that_fit() {
+        let image = DynamicImage::new_rgb8(800, 1200);
+        assert!(fit_within(&image, 800, 1200).is_none());
+        assert!(fit_within(&image, 1000, 1500).is_none());
+    }
+
+    #[test]
+    fn scales_down_keeping_the_aspect_ratio() {
+        let image = DynamicImage::new_rgb8(2000, 1000);
+        let resized = fit_within(&image, 500, 500).unwrap();
+        assert_eq!((resized.width(), resized.height()), (500, 250));
+
+        let image = DynamicImage::new_rgb8(1000, 3000);
+        let resized = fit_within(&image, 1072, 1448).unwrap();
+        assert_eq!((resized.width(), resized.height()), (483, 1448));
+    }
+}
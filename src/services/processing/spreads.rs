@@ -0,0 +1,156 @@
+use image::DynamicImage;
+
+use crate::models::export::{ReadingDirection, SpreadMode};
+
+/// Pages at least this much wider than tall are treated as double-page spreads
+const SPREAD_ASPECT_RATIO: f64 = 1.2;
+
+pub fn is_spread(image: &DynamicImage) -> bool {
+    image.width() as f64 / image.height() as f64 >= SPREAD_ASPECT_RATIO
+}
+
+/// Returns the pages a spread turns into, in reading order. None when the page should be
+/// left alone.
+pub fn handle_spread(
+    image: &DynamicImage,
+    mode: SpreadMode,
+    direction: ReadingDirection,
+) -> Option<Vec<DynamicImage>> {
+    if !is_spread(image) {
+        return None;
+    }
+    match mode {
+        SpreadMode::Keep => None,
+        SpreadMode::Split => Some(split_halves(image, direction)),
+        // Turn it so the half that's read first ends up on top
+        SpreadMode::Rotate => Some(vec![match direction {
+            ReadingDirection::LeftToRight => image.rotate90(),
+            ReadingDirection::RightToLeft => image.rotate270(),
+        }]),
+        SpreadMode::SplitAndKeep => {
+            let mut pages = vec![image.clone()];
+            pages.extend(split_halves(image, direction));
+            Some(pages)
+        }
+    }
+}
+
+fn split_halves(image: &DynamicImage, direction: ReadingDirection) -> Vec<DynamicImage> {
+    let half = image.width() / 2;
+    let left = image.crop_imm(0, 0, half, image.height());
+    let right = image.crop_imm(half, 0, image.width() - half, image.height());
+    match direction {
+        ReadingDirection::LeftToRight => vec![left, right],
+        ReadingDirection::RightToLeft => vec![right, left],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Rgb, RgbImage};
+
+    use super::*;
+
+    const LEFT: Rgb<u8> = Rgb([255, 0, 0]);
+    const RIGHT: Rgb<u8> = Rgb([0, 0, 255]);
+
+    /// Red on the left half, blue on the right
+    fn spread(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                LEFT
+            } else {
+                RIGHT
+            }
+        }))
+    }
+
+    fn color(image: &DynamicImage) -> Rgb<u8> {
+        *image.to_rgb8().get_pixel(0, 0)
+    }
+
+    #[test]
+    fn detects_spreads_by_aspect_ratio() {
+        assert!(!is_spread(&spread(100, 150)));
+        assert!(!is_spread(&spread(119, 100)));
+        assert!(is_spread(&spread(120, 100)));
+        assert!(is_spread(&spread(200, 100)));
+    }
+
+    #[test]
+    fn leaves_single_pages_alone() {
+        let page = spread(100, 150);
+        for mode in [
+            SpreadMode::Split,
+            SpreadMode::Rotate,
+            SpreadMode::SplitAndKeep,
+        ] {
+            assert!(handle_spread(&page, mode, ReadingDirection::LeftToRight).is_none());
+        }
+        assert!(handle_spread(
+            &spread(200, 100),
+            SpreadMode::Keep,
+            ReadingDirection::LeftToRight
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn splits_in_reading_order() {
+        let image = spread(200, 100);
+        let pages =
+            handle_spread(&image, SpreadMode::Split, ReadingDirection::LeftToRight).unwrap();
+        assert_eq!(
+            pages.iter().map(color).collect::<Vec<_>>(),
+            vec![LEFT, RIGHT]
+        );
+        assert!(pages.iter().all(|page| page.dimensions() == (100, 100)));
+
+        let pages =
+            handle_spread(&image, SpreadMode::Split, ReadingDirection::RightToLeft).unwrap();
+        assert_eq!(
+            pages.iter().map(color).collect::<Vec<_>>(),
+            vec![RIGHT, LEFT]
+        );
+    }
+
+    #[test]
+    fn splits_odd_widths_without_losing_a_column() {
+        let pages = handle_spread(
+            &spread(201, 100),
+            SpreadMode::Split,
+            ReadingDirection::LeftToRight,
+        )
+        .unwrap();
+        assert_eq!(pages[0].width() + pages[1].width(), 201);
+    }
+
+    #[test]
+    fn rotates_the_first_half_to_the_top() {
+        let image = spread(200, 100);
+        let pages =
+            handle_spread(&image, SpreadMode::Rotate, ReadingDirection::LeftToRight).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].dimensions(), (100, 200));
+        assert_eq!(color(&pages[0]), LEFT);
+
+        let pages =
+            handle_spread(&image, SpreadMode::Rotate, ReadingDirection::RightToLeft).unwrap();
+        assert_eq!(color(&pages[0]), RIGHT);
+    }
+
+    #[test]
+    fn keeps_the_whole_spread_before_its_halves() {
+        let image = spread(200, 100);
+        let pages = handle_spread(
+            &image,
+            SpreadMode::SplitAndKeep,
+            ReadingDirection::RightToLeft,
+        )
+        .unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].dimensions(), (200, 100));
+        assert_eq!(color(&pages[1]), RIGHT);
+        assert_eq!(color(&pages[2]), LEFT);
+    }
+}
@@ -0,0 +1,223 @@
+use image::{imageops, DynamicImage, RgbaImage};
+
+/// Page height over width when the export has no target device
+pub const DEFAULT_PAGE_RATIO: f64 = 4.0 / 3.0;
+
+/// How far up from the ideal cut we look for a blank band, as a share of the page height
+const SEARCH_WINDOW: f64 = 0.25;
+/// Luma difference from the row's first pixel that still counts as background
+const BLANK_THRESHOLD: i32 = 16;
+/// Rows with fewer differing pixels than this share are treated as blank
+const NOISE_RATIO: f64 = 0.01;
+/// Strips are only cut once they're this much taller than a page, so the last page of a
+/// chapter isn't a sliver
+const CARRY_FACTOR: f64 = 1.25;
+
+/// Stitches the images of a chapter into one long strip and cuts it into screen-shaped
+/// pages as it goes. Only the uncut remainder is kept in memory.
+pub struct StripSlicer {
+    page_ratio: f64,
+    carry: Option<RgbaImage>,
+}
+
+impl StripSlicer {
+    pub fn new(page_ratio: f64) -> Self {
+        StripSlicer {
+            page_ratio,
+            carry: None,
+        }
+    }
+
+    /// Appends the image to the strip and returns the pages that are complete
+    pub fn push(&mut self, image: &DynamicImage) -> Vec<DynamicImage> {
+        let mut pages = Vec::new();
+        let image = image.to_rgba8();
+        let strip = match self.carry.take() {
+            Some(carry) if carry.width() == image.width() => stitch(&carry, &image),
+            Some(carry) => {
+                // A change in width ends the strip, there's nothing sensible to stitch
+                pages.extend(self.slice(carry, 1.0));
+                image
+            }
+            None => image,
+        };
+        pages.extend(self.slice(strip, CARRY_FACTOR));
+        pages
+    }
+
+    /// Cuts whatever is left, when the chapter has no more images or the strip is interrupted
+    pub fn finish(&mut self) -> Vec<DynamicImage> {
+        match self.carry.take() {
+            Some(carry) => self.slice(carry, 1.0),
+            None => Vec::new(),
+        }
+    }
+
+    /// Cuts pages off the top while the strip is taller than `carry_factor` pages. The rest
+    /// is kept as the carry, or returned too when `carry_factor` is 1.
+    fn slice(&mut self, mut strip: RgbaImage, carry_factor: f64) -> Vec<DynamicImage> {
+        let page_height = ((strip.width() as f64 * self.page_ratio).round() as u32).max(1);
+        let mut pages = Vec::new();
+        while strip.height() as f64 > page_height as f64 * carry_factor {
+            let cut = find_cut(&strip, page_height);
+            pages.push(DynamicImage::ImageRgba8(
+                imageops::crop_imm(&strip, 0, 0, strip.width(), cut).to_image(),
+            ));
+            strip =
+                imageops::crop_imm(&strip, 0, cut, strip.width(), strip.height() - cut).to_image();
+        }
+        if carry_factor > 1.0 {
+            self.carry = Some(strip);
+        } else if strip.height() > 0 {
+            pages.push(DynamicImage::ImageRgba8(strip));
+        }
+        pages
+    }
+}
+
+fn stitch(top: &RgbaImage, bottom: &RgbaImage) -> RgbaImage {
+    let mut strip = RgbaImage::new(top.width(), top.height() + bottom.height());
+    imageops::replace(&mut strip, top, 0, 0);
+    imageops::replace(&mut strip, bottom, 0, top.height() as i64);
+    strip
+}
+
+/// Picks the row to cut at: the lowest blank row within the search window above the ideal
+/// cut, otherwise the row with the least going on
+fn find_cut(strip: &RgbaImage, page_height: u32) -> u32 {
+    let ideal = page_height.min(strip.height());
+    let lowest = ideal - (page_height as f64 * SEARCH_WINDOW) as u32;
+    let blank_limit = (strip.width() as f64 * NOISE_RATIO) as usize;
+
+    let mut best = (usize::MAX, ideal);
+    for y in (lowest.max(1)..ideal).rev() {
+        let busy = busy_pixels(strip, y);
+        if busy <= blank_limit {
+            return y;
+        }
+        if busy < best.0 {
+            best = (busy, y);
+        }
+    }
+    best.1
+}
+
+fn busy_pixels(strip: &RgbaImage, y: u32) -> usize {
+    let luma = |x: u32| {
+        let [r, g, b, _] = strip.get_pixel(x, y).0;
+        (r as i32 * 299 + g as i32 * 587 + b as i32 * 114) / 1000
+    };
+    let background = luma(0);
+    (0..strip.width())
+        .filter(|x| (luma(*x) - background).abs() > BLANK_THRESHOLD)
+        .count()
+}
+
+/// Page height over width for a target resolution
+pub fn page_ratio(target_resolution: Option<(u32, u32)>) -> f64 {
+    match target_resolution {
+        Some((width, height)) if width > 0 => height as f64 / width as f64,
+        _ => DEFAULT_PAGE_RATIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    /// A checkerboard strip, with the rows in `blank` left white
+    fn strip(width: u32, height: u32, blank: std::ops::Range<u32>) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            if blank.contains(&y) || (x + y) % 2 == 0 {
+                WHITE
+            } else {
+                BLACK
+            }
+        })
+    }
+
+    fn heights(pages: &[DynamicImage]) -> Vec<u32> {
+        pages.iter().map(|page| page.height()).collect()
+    }
+
+    #[test]
+    fn cuts_at_the_lowest_blank_row() {
+        let strip = strip(100, 1000, 350..360);
+        assert_eq!(find_cut(&strip, 400), 359);
+    }
+
+    #[test]
+    fn ignores_blank_rows_outside_the_search_window() {
+        // The window reaches 100 rows above the ideal cut at 400
+        let strip = strip(100, 1000, 200..210);
+        assert_eq!(find_cut(&strip, 400), 399);
+    }
+
+    #[test]
+    fn cuts_at_the_quietest_row_without_a_blank_band() {
+        let mut strip = strip(100, 1000, 0..0);
+        for x in 0..50 {
+            strip.put_pixel(x, 380, WHITE);
+        }
+        assert_eq!(find_cut(&strip, 400), 380);
+    }
+
+    #[test]
+    fn tolerates_noise_in_blank_rows() {
+        let mut strip = strip(200, 1000, 350..360);
+        strip.put_pixel(10, 355, BLACK);
+        assert_eq!(find_cut(&strip, 400), 359);
+    }
+
+    #[test]
+    fn carries_the_remainder_across_pushes() {
+        // 30 wide at 4:3 makes 40 high pages, which are only cut past 50. Without a blank
+        // band the cut lands on the row above the ideal one.
+        let mut slicer = StripSlicer::new(4.0 / 3.0);
+        let image = DynamicImage::ImageRgba8(strip(30, 30, 0..0));
+        assert!(slicer.push(&image).is_empty());
+
+        let pages = slicer.push(&image);
+        assert_eq!(heights(&pages), vec![39]);
+        assert_eq!(slicer.carry.as_ref().map(|carry| carry.height()), Some(21));
+
+        assert_eq!(heights(&slicer.finish()), vec![21]);
+        assert!(slicer.carry.is_none());
+        assert!(slicer.finish().is_empty());
+    }
+
+    #[test]
+    fn finish_cuts_the_whole_remainder() {
+        let mut slicer = StripSlicer::new(4.0 / 3.0);
+        let image = DynamicImage::ImageRgba8(strip(30, 45, 0..0));
+        assert!(slicer.push(&image).is_empty());
+        let pages = slicer.finish();
+        assert_eq!(heights(&pages), vec![39, 6]);
+        assert!(pages.iter().all(|page| page.width() == 30));
+    }
+
+    #[test]
+    fn width_change_ends_the_strip() {
+        let mut slicer = StripSlicer::new(4.0 / 3.0);
+        assert!(slicer
+            .push(&DynamicImage::ImageRgba8(strip(30, 30, 0..0)))
+            .is_empty());
+        let pages = slicer.push(&DynamicImage::ImageRgba8(strip(40, 10, 0..0)));
+        assert_eq!(heights(&pages), vec![30]);
+        let pages = slicer.finish();
+        assert_eq!(heights(&pages), vec![10]);
+        assert_eq!(pages[0].width(), 40);
+    }
+
+    #[test]
+    fn page_ratio_from_resolution() {
+        assert_eq!(page_ratio(Some((1000, 1500))), 1.5);
+        assert_eq!(page_ratio(Some((0, 1500))), DEFAULT_PAGE_RATIO);
+        assert_eq!(page_ratio(None), DEFAULT_PAGE_RATIO);
+    }
+}
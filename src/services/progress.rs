@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tokio::{
+    sync::broadcast,
+    task::JoinHandle,
+    time::{interval, Duration},
+};
+
+use crate::{
+    models::export::{set_export_progress, ExportState, ExportStep},
+    AppError,
+};
+
+/// How often the progress of running exports is written to the database
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+/// Updates a slow subscriber can fall behind by before it skips ahead
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Where an export is at, sent to the progress streams. `progress` counts chapters or pages
+/// depending on the step, out of `progress_total`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressUpdate {
+    pub export_id: i64,
+    pub state: ExportState,
+    pub step: ExportStep,
+    pub state_label: String,
+    pub step_label: String,
+    pub progress: i64,
+    pub progress_total: i64,
+}
+
+impl ProgressUpdate {
+    pub fn new(
+        export_id: i64,
+        state: ExportState,
+        step: ExportStep,
+        progress: i64,
+        progress_total: i64,
+    ) -> Self {
+        ProgressUpdate {
+            export_id,
+            state,
+            step,
+            state_label: state.to_string(),
+            step_label: step.to_string(),
+            progress,
+            progress_total,
+        }
+    }
+}
+
+struct Tracker {
+    current: HashMap<i64, ProgressUpdate>,
+    sender: broadcast::Sender<ProgressUpdate>,
+}
+
+fn tracker() -> &'static Mutex<Tracker> {
+    static TRACKER: OnceLock<Mutex<Tracker>> = OnceLock::new();
+    TRACKER.get_or_init(|| {
+        Mutex::new(Tracker {
+            current: HashMap::new(),
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        })
+    })
+}
+
+/// Applies the change to the export's progress, if it's being tracked, and sends it out
+fn update(export_id: i64, change: impl FnOnce(&mut ProgressUpdate)) {
+    let mut tracker = tracker().lock().unwrap();
+    let Some(progress) = tracker.current.get_mut(&export_id) else {
+        return;
+    };
+    change(progress);
+    let progress = progress.clone();
+    // Nobody listening isn't an error
+    let _ = tracker.sender.send(progress);
+}
+
+/// Resets the counters for a new step
+pub fn start_step(export_id: i64, state: ExportState, step: ExportStep, total: i64) {
+    let mut tracker = tracker().lock().unwrap();
+    let progress = ProgressUpdate::new(export_id, state, step, 0, total);
+    tracker.current.insert(export_id, progress.clone());
+    let _ = tracker.sender.send(progress);
+}
+
+pub fn add_total(export_id: i64, count: i64) {
+    update(export_id, |progress| progress.progress_total += count);
+}
+
+pub fn advance(export_id: i64, count: i64) {
+    update(export_id, |progress| progress.progress += count);
+}
+
+pub fn set_done(export_id: i64, done: i64) {
+    update(export_id, |progress| progress.progress = done);
+}
+
+/// Sends out a state change, e.g. when the export completes or fails. Exports without a
+/// running task, like paused ones being cancelled, are sent out without counters.
+pub fn set_state(export_id: i64, state: ExportState, step: ExportStep) {
+    let mut tracker = tracker().lock().unwrap();
+    let progress = match tracker.current.get_mut(&export_id) {
+        Some(progress) => {
+            *progress = ProgressUpdate::new(
+                export_id,
+                state,
+                step,
+                progress.progress,
+                progress.progress_total,
+            );
+            progress.clone()
+        }
+        None => ProgressUpdate::new(export_id, state, step, 0, 0),
+    };
+    let _ = tracker.sender.send(progress);
+}
+
+pub fn get_progress(export_id: i64) -> Option<ProgressUpdate> {
+    tracker().lock().unwrap().current.get(&export_id).cloned()
+}
+
+pub fn subscribe() -> broadcast::Receiver<ProgressUpdate> {
+    tracker().lock().unwrap().sender.subscribe()
+}
+
+pub async fn save_progress(pool: &SqlitePool, export_id: i64) -> Result<(), AppError> {
+    if let Some(progress) = get_progress(export_id) {
+        set_export_progress(pool, export_id, progress.progress, progress.progress_total).await?;
+    }
+    Ok(())
+}
+
+/// Stops tracking once the export's task is done, after saving where it got to
+pub async fn finish_tracking(pool: &SqlitePool, export_id: i64) -> Result<(), AppError> {
+    save_progress(pool, export_id).await?;
+    tracker().lock().unwrap().current.remove(&export_id);
+    Ok(())
+}
+
+/// Saves the progress in the background while the export runs, until dropped
+pub struct ProgressSaver(JoinHandle<()>);
+
+impl ProgressSaver {
+    pub fn spawn(pool: Arc<SqlitePool>, export_id: i64) -> Self {
+        ProgressSaver(tokio::spawn(async move {
+            let mut ticker = interval(SAVE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = save_progress(&pool, export_id).await {
+                    println!("Couldn't save progress of export {}: {}", export_id, e);
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for ProgressSaver {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::HashSet, fs};
+
+use crate::{
+    models::{
+        export::{Export, SplitMode},
+        export_part::NewExportPart,
+    },
+    services::assemblers::get_export_chapter_pages,
+    suwayomi::chapters_by_ids::ChaptersByIdsChaptersNodes,
+    util::parse_volume_number,
+    AppError,
+};
+
+/// Chapters going into one file, with the volume number when splitting by volume
+type ChapterGroup = (Option<i64>, Vec<i64>);
+
+/// Groups the chapters of an export into the files it will produce, in reading order.
+/// Splitting that ends up with a single group produces the same file as not splitting.
+pub fn plan_export_parts(
+    export: &Export,
+    chapters: &[ChaptersByIdsChaptersNodes],
+) -> Result<Vec<(NewExportPart, HashSet<i64>)>, AppError> {
+    let split_value = export.split_value.filter(|value| *value > 0);
+    let groups: Vec<ChapterGroup> = match (export.split_mode, split_value) {
+        (SplitMode::ChapterCount, Some(count)) => chapters
+            .chunks(count as usize)
+            .map(|chunk| (None, chunk.iter().map(|chapter| chapter.id).collect()))
+            .collect(),
+        (SplitMode::FileSize, Some(megabytes)) => {
+            split_by_size(export.id, chapters, megabytes * 1024 * 1024)?
+        }
+        (SplitMode::Volume, _) => split_by_volume(chapters),
+        _ => vec![(None, chapters.iter().map(|chapter| chapter.id).collect())],
+    };
+
+    if groups.len() <= 1 {
+        let chapter_ids = groups.into_iter().flat_map(|(_, ids)| ids).collect();
+        return Ok(vec![(
+            NewExportPart {
+                title: export.title.to_owned(),
+                filename: export.get_filename(),
+                series_index: None,
+            },
+            chapter_ids,
+        )]);
+    }
+
+    Ok(groups
+        .into_iter()
+        .enumerate()
+        .map(|(index, (volume, chapter_ids))| {
+            let number = index as i64 + 1;
+            let title = match volume {
+                Some(volume) => format!("{} - Vol. {}", export.title, volume),
+                None => format!("{} - Part {}", export.title, number),
+            };
+            let part = NewExportPart {
+                filename: format!("{}.{}", title, export.format.to_extension()),
+                title,
+                series_index: Some(volume.unwrap_or(number)),
+            };
+            (part, chapter_ids.into_iter().collect())
+        })
+        .collect())
+}
+
+/// Starts a new part whenever adding the next chapter would go over the target size.
+/// A single chapter bigger than the target still gets a part of its own.
+fn split_by_size(
+    export_id: i64,
+    chapters: &[ChaptersByIdsChaptersNodes],
+    target_bytes: i64,
+) -> Result<Vec<ChapterGroup>, AppError> {
+    let mut groups: Vec<ChapterGroup> = Vec::new();
+    let mut current_size = 0;
+    for chapter in chapters {
+        let mut chapter_size = 0;
+        for page in get_export_chapter_pages(export_id, chapter.id)? {
+            chapter_size += fs::metadata(page)?.len() as i64;
+        }
+        match groups.last_mut() {
+            Some((_, ids)) if current_size + chapter_size <= target_bytes => {
+                ids.push(chapter.id);
+                current_size += chapter_size;
+            }
+            _ => {
+                groups.push((None, vec![chapter.id]));
+                current_size = chapter_size;
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Chapters without a volume in their name stay with the chapter before them
+fn split_by_volume(chapters: &[ChaptersByIdsChaptersNodes]) -> Vec<ChapterGroup> {
+    let mut groups: Vec<ChapterGroup> = Vec::new();
+    let mut current: Option<usize> = None;
+    for chapter in chapters {
+        let index = match parse_volume_number(&chapter.name) {
+            Some(volume) => match groups.iter().position(|(v, _)| *v == Some(volume)) {
+                Some(index) => index,
+                None => {
+                    groups.push((Some(volume), Vec::new()));
+                    groups.len() - 1
+                }
+            },
+            None => match current {
+                Some(index) => index,
+                None => {
+                    groups.push((None, Vec::new()));
+                    groups.len() - 1
+                }
+            },
+        };
+        groups[index].1.push(chapter.id);
+        current = Some(index);
+    }
+    groups
+}
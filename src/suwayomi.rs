@@ -1,19 +1,43 @@
 use std::fs;
 use std::io::{copy, Cursor};
-use std::path::{Path, PathBuf};
-use std::{collections::HashSet, env};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+};
 
 use anyhow::{Error, Result};
 use eyre::eyre;
 use graphql_client::{reqwest::post_graphql, GraphQLQuery};
-use tokio::time::{sleep, Duration};
+use tokio::{
+    sync::Semaphore,
+    time::{sleep, timeout, Duration},
+};
 
-use futures::future::join_all;
-use regex::Regex;
+use futures::{future::join_all, SinkExt, StreamExt};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
 
 use crate::{
+    models::{
+        chapter_cache::{record_cached_chapter, CachedChapterInfo},
+        chapter_page::{
+            set_page_downloaded, set_page_failed, sync_chapter_manifest, ChapterPage,
+            DownloadedPage, PageStatus,
+        },
+        export::{clear_chapter_downloads_queued, set_chapter_downloads_queued, ExportStep},
+        export_log::{log_export, LogLevel},
+    },
+    services::{control::check_interrupted, progress},
     suwayomi::check_on_download_progress::DownloaderState,
-    util::join_url,
+    util::{env_or, join_url, sha256_hex, sniff_image_extension},
     AppError,
 };
 
@@ -47,8 +71,10 @@ pub async fn get_library() -> Result<Vec<get_library::GetLibraryMangasNodes>, Ap
     query_path = "graphql/queries/AllSourcesByLanguage.graphql",
     response_derives = "Debug,Serialize"
 )]
+#[allow(dead_code)]
 pub struct AllSourcesByLanguage;
 
+#[allow(dead_code)]
 pub async fn get_all_sources_by_lang(
     variables: all_sources_by_language::Variables,
 ) -> Result<Vec<all_sources_by_language::AllSourcesByLanguageSourcesNodes>, Error> {
@@ -74,8 +100,10 @@ type LongString = String;
     query_path = "graphql/queries/MangaSourceSearch.graphql",
     response_derives = "Debug,Serialize"
 )]
+#[allow(dead_code)]
 pub struct MangaSourceSearch;
 
+#[allow(dead_code)]
 pub async fn search_manga_by_title(
     variables: manga_source_search::Variables,
 ) -> Result<Vec<manga_source_search::MangaSourceSearchFetchSourceMangaMangas>, AppError> {
@@ -162,6 +190,28 @@ pub struct CheckChaptersDownloaded;
 )]
 pub struct DownloadChapters;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.json",
+    query_path = "graphql/queries/DequeueChapterDownloads.graphql",
+    response_derives = "Debug,Clone"
+)]
+pub struct DequeueChapterDownloads;
+
+/// Takes chapters back out of the Suwayomi download queue, e.g. when their export is stopped
+pub async fn dequeue_chapter_downloads(ids: &HashSet<i64>) -> Result<(), AppError> {
+    let client = reqwest::Client::new();
+    post_graphql::<DequeueChapterDownloads, _>(
+        &client,
+        join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?,
+        dequeue_chapter_downloads::Variables {
+            ids: ids.iter().cloned().collect(),
+        },
+    )
+    .await?;
+    Ok(())
+}
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "graphql/schema.json",
@@ -171,6 +221,8 @@ pub struct DownloadChapters;
 pub struct CheckOnDownloadProgress;
 
 pub async fn download_chapters_from_source(
+    pool: &SqlitePool,
+    export_id: i64,
     ids: &HashSet<i64>,
 ) -> Result<(), AppError> {
     let client = reqwest::Client::new();
@@ -198,86 +250,422 @@ pub async fn download_chapters_from_source(
 
     dbg!(&chapters_to_download);
 
-    if chapters_to_download.len() == 0 {
+    if chapters_to_download.is_empty() {
         println!("Skipped downloading chapters from source - all chapters already downloaded");
         return Ok(());
     }
 
-    let res = post_graphql::<DownloadChapters, _>(
+    progress::add_total(export_id, chapters_to_download.len() as i64);
+    // Subscribe before queueing so none of the changes are missed
+    let subscription = match DownloadSubscription::connect().await {
+        Ok(subscription) => Some(subscription),
+        Err(e) => {
+            println!("Download subscription unavailable, polling instead: {}", e);
+            None
+        }
+    };
+
+    let _res = post_graphql::<DownloadChapters, _>(
         &client,
         join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?,
         download_chapters::Variables {
-            ids: chapters_to_download,
+            ids: chapters_to_download.clone(),
         },
     )
     .await?;
+    set_chapter_downloads_queued(pool, export_id, &chapters_to_download).await?;
+
+    wait_for_chapter_downloads(export_id, &client, &chapters_to_download, subscription).await?;
+    clear_chapter_downloads_queued(pool, export_id).await?;
+
+    println!("download from source complete");
+
+    Ok(())
+}
+
+/// How often the downloader is polled without a subscription, and how often a subscribed
+/// export checks whether it was paused or cancelled
+const DOWNLOAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const SUBSCRIPTION_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.json",
+    query_path = "graphql/queries/DownloadChanged.graphql",
+    response_derives = "Debug,Clone,PartialEq"
+)]
+pub struct DownloadChanged;
+
+/// The parts of the downloader's status an export looks at, whether it was polled or came
+/// from the subscription
+struct DownloadQueueStatus {
+    stopped: bool,
+    /// Chapters in the queue and whether their download failed
+    queue: HashMap<i64, bool>,
+}
+
+impl From<check_on_download_progress::ResponseData> for DownloadQueueStatus {
+    fn from(data: check_on_download_progress::ResponseData) -> Self {
+        use check_on_download_progress::DownloadState;
+        DownloadQueueStatus {
+            stopped: data.download_status.state == DownloaderState::STOPPED,
+            queue: data
+                .download_status
+                .queue
+                .into_iter()
+                .map(|item| (item.chapter.id, item.state == DownloadState::ERROR))
+                .collect(),
+        }
+    }
+}
+
+impl From<download_changed::ResponseData> for DownloadQueueStatus {
+    fn from(data: download_changed::ResponseData) -> Self {
+        use download_changed::{DownloadState, DownloaderState};
+        DownloadQueueStatus {
+            stopped: data.download_changed.state == DownloaderState::STOPPED,
+            queue: data
+                .download_changed
+                .queue
+                .into_iter()
+                .map(|item| (item.chapter.id, item.state == DownloadState::ERROR))
+                .collect(),
+        }
+    }
+}
+
+async fn get_download_queue_status(
+    client: &reqwest::Client,
+) -> Result<DownloadQueueStatus, AppError> {
+    match post_graphql::<CheckOnDownloadProgress, _>(
+        client,
+        join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?,
+        check_on_download_progress::Variables {},
+    )
+    .await?
+    .data
+    {
+        Some(data) => Ok(data.into()),
+        None => Err(eyre!("Missing response data").into()),
+    }
+}
+
+/// Waits until the chapters this export queued have left Suwayomi's download queue, so
+/// downloads queued by other clients don't hold it up. Chapters whose download failed count
+/// as done, their pages are fetched one by one in the next step.
+async fn wait_for_chapter_downloads(
+    export_id: i64,
+    client: &reqwest::Client,
+    chapters: &[i64],
+    mut subscription: Option<DownloadSubscription>,
+) -> Result<(), AppError> {
+    // The subscription only sends changes, so start from the current status
+    let mut status = get_download_queue_status(client).await?;
     loop {
-        let downloader_state = match post_graphql::<CheckOnDownloadProgress, _>(
-            &client,
-            join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?,
-            check_on_download_progress::Variables {},
-        )
-        .await?
-        .data
-        {
-            Some(data) => data,
-            None => break,
+        let remaining = chapters
+            .iter()
+            .filter(|id| status.queue.get(id) == Some(&false))
+            .count();
+        progress::set_done(export_id, (chapters.len() - remaining) as i64);
+        if remaining == 0 {
+            return Ok(());
+        }
+        if status.stopped {
+            println!(
+                "Downloader stopped with {} chapters of export {} still queued",
+                remaining, export_id
+            );
+            return Ok(());
+        }
+
+        status = loop {
+            check_interrupted(export_id)?;
+            let Some(active) = subscription.as_mut() else {
+                sleep(DOWNLOAD_POLL_INTERVAL).await;
+                break get_download_queue_status(client).await?;
+            };
+            match timeout(DOWNLOAD_POLL_INTERVAL, active.next_status()).await {
+                Ok(Ok(status)) => break status,
+                Ok(Err(e)) => {
+                    println!("Download subscription failed, polling instead: {}", e);
+                    subscription = None;
+                }
+                // Nothing changed, go round to check whether the export was stopped
+                Err(_) => {}
+            }
         };
-        dbg!(&downloader_state.download_status.queue.first());
-        dbg!(&downloader_state.download_status.queue.len());
-        if downloader_state.download_status.state == DownloaderState::STOPPED {
-            break;
+    }
+}
+
+/// A `downloadChanged` subscription. Suwayomi has spoken both the graphql-transport-ws
+/// protocol and the older graphql-ws one, whichever it picks is used.
+struct DownloadSubscription {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    legacy: bool,
+}
+
+impl DownloadSubscription {
+    async fn connect() -> Result<Self, AppError> {
+        let mut url = join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|_| eyre!("Can't make a websocket URL from {}", url))?;
+        let mut request = url.as_str().into_client_request()?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static("graphql-transport-ws, graphql-ws"),
+        );
+        let (socket, response) =
+            timeout(SUBSCRIPTION_CONNECT_TIMEOUT, connect_async(request)).await??;
+        let legacy = response
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .is_some_and(|protocol| protocol == "graphql-ws");
+        let mut subscription = DownloadSubscription { socket, legacy };
+
+        subscription
+            .send(json!({ "type": "connection_init", "payload": {} }))
+            .await?;
+        let ack = timeout(SUBSCRIPTION_CONNECT_TIMEOUT, subscription.receive()).await??;
+        if ack["type"] != "connection_ack" {
+            return Err(eyre!("Subscription wasn't accepted: {}", ack).into());
         }
+        let start = if legacy { "start" } else { "subscribe" };
+        let query = DownloadChanged::build_query(download_changed::Variables {});
+        subscription
+            .send(json!({ "id": "1", "type": start, "payload": query }))
+            .await?;
+        Ok(subscription)
+    }
 
-        // Wait for a specified interval before polling again
-        sleep(Duration::from_secs(2)).await;
+    async fn send(&mut self, message: serde_json::Value) -> Result<(), AppError> {
+        self.socket.send(Message::Text(message.to_string())).await?;
+        Ok(())
     }
 
-    println!("download from source complete");
+    /// The next protocol message, answering pings and skipping keep-alives on the way
+    async fn receive(&mut self) -> Result<serde_json::Value, AppError> {
+        loop {
+            let message = match self.socket.next().await {
+                Some(message) => message?,
+                None => return Err(eyre!("Subscription closed").into()),
+            };
+            match message {
+                Message::Text(text) => {
+                    let message: serde_json::Value = serde_json::from_str(&text)?;
+                    match message["type"].as_str() {
+                        Some("ping") if !self.legacy => {
+                            self.send(json!({ "type": "pong" })).await?
+                        }
+                        Some("ka") | Some("pong") => {}
+                        _ => return Ok(message),
+                    }
+                }
+                Message::Close(_) => return Err(eyre!("Subscription closed").into()),
+                // Websocket pings are answered by tungstenite
+                _ => {}
+            }
+        }
+    }
 
-    Ok(())
+    async fn next_status(&mut self) -> Result<DownloadQueueStatus, AppError> {
+        let message = self.receive().await?;
+        match message["type"].as_str() {
+            Some("next") | Some("data") => {
+                let response: graphql_client::Response<download_changed::ResponseData> =
+                    serde_json::from_value(message["payload"].clone())?;
+                match response.data {
+                    Some(data) => Ok(data.into()),
+                    None => Err(eyre!("Missing response data: {:?}", response.errors).into()),
+                }
+            }
+            _ => Err(eyre!("Subscription ended: {}", message).into()),
+        }
+    }
 }
 
-async fn dl_img(url: &str, dl_dir: &PathBuf) -> Result<(), AppError> {
-    // let client = reqwest::Client::new();
-    let re = Regex::new(r"/api/v1/manga/\d+/chapter/\d+/page/(\d+)").unwrap();
-    let Some(caps) = re.captures(url) else {
-        return Err(eyre!("Couldn't parse image url").into());
-    };
-    
-    std::fs::create_dir_all(dl_dir)?;
-    let response = reqwest::get(join_url(&env::var("SUWAYOMI_URL")?, url)?).await?;
-    let content_type = response
-        .headers()
-        .get("Content-Type")
-        .unwrap()
-        .to_str()
-        .unwrap();
-    if !content_type.starts_with("image/") {
-        return Err(eyre!("Not an image: {:?} (downloading {})", content_type, url).into());
+/// Limits for page downloads, read once from the environment
+struct PageDownloadSettings {
+    /// Page requests in flight at once, across every running export
+    concurrency: usize,
+    timeout: Duration,
+    /// Attempts after the first one
+    retries: u32,
+    /// Delay before the first retry, doubled for every retry after it
+    backoff: Duration,
+}
+
+/// Backoff never grows past this
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn page_download_settings() -> &'static PageDownloadSettings {
+    static SETTINGS: OnceLock<PageDownloadSettings> = OnceLock::new();
+    SETTINGS.get_or_init(|| PageDownloadSettings {
+        concurrency: env_or("PAGE_DOWNLOAD_CONCURRENCY", 4).max(1),
+        timeout: Duration::from_secs(env_or("PAGE_DOWNLOAD_TIMEOUT_SECS", 30)),
+        retries: env_or("PAGE_DOWNLOAD_RETRIES", 3),
+        backoff: Duration::from_millis(env_or("PAGE_DOWNLOAD_BACKOFF_MS", 500)),
+    })
+}
+
+fn page_download_permits() -> &'static Semaphore {
+    static PERMITS: OnceLock<Semaphore> = OnceLock::new();
+    PERMITS.get_or_init(|| Semaphore::new(page_download_settings().concurrency))
+}
+
+fn page_download_client() -> Result<&'static reqwest::Client, AppError> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
     }
-    let extension = match content_type.split("/").last() {
-        Some(ext) => ext,
-        None => return Err(eyre!("Couldn't parse image extension").into()),
+    let client = reqwest::Client::builder()
+        .timeout(page_download_settings().timeout)
+        .build()?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// Downloads one page into the chapter directory as `{page_index}.{ext}`
+async fn dl_img(url: &str, dl_dir: &Path, page_index: i64) -> Result<DownloadedPage, AppError> {
+    std::fs::create_dir_all(dl_dir)?;
+    let response = page_download_client()?
+        .get(join_url(&env::var("SUWAYOMI_URL")?, url)?)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = match response.headers().get("Content-Type") {
+        Some(content_type) => content_type.to_str()?.to_string(),
+        None => String::new(),
     };
-    let mut file = match std::fs::File::create(dl_dir.join(format!("{}.{}", &caps[1], &extension))) {
+    let bytes = response.bytes().await?;
+    // Servers often get the Content-Type wrong, so trust the magic bytes when we know them
+    let extension = match sniff_image_extension(&bytes) {
+        Some(extension) => extension.to_string(),
+        None => match content_type.strip_prefix("image/") {
+            Some(extension) if !extension.is_empty() => extension.to_string(),
+            _ => {
+                return Err(eyre!("Not an image: {:?} (downloading {})", content_type, url).into())
+            }
+        },
+    };
+    let filename = format!("{}.{}", page_index, extension);
+    // Exports hard link the raw pages into their processed output, so a re-fetched page is
+    // written next to the old one and renamed over it rather than rewriting the shared file
+    let temp_path = dl_dir.join(format!("{}.part", filename));
+    let mut file = match std::fs::File::create(&temp_path) {
         Ok(f) => f,
         Err(e) => {
             println!("Couldn't create file: {:?}", e);
             return Err(eyre!("Couldn't create file: {:?}", e).into());
         }
     };
-    let mut content = Cursor::new(response.bytes().await?);
+    let downloaded = DownloadedPage {
+        content_type: format!("image/{}", extension),
+        byte_size: bytes.len() as i64,
+        checksum: sha256_hex(&bytes),
+        filename,
+        attempts: 1,
+    };
+    let mut content = Cursor::new(bytes);
     copy(&mut content, &mut file)?;
-    Ok(())
+    drop(file);
+    fs::rename(&temp_path, dl_dir.join(&downloaded.filename))?;
+    Ok(downloaded)
+}
+
+/// A page only counts as downloaded when its file is still there and matches the manifest
+fn is_page_intact(page: &ChapterPage, dl_dir: &Path) -> bool {
+    if page.status != PageStatus::Downloaded {
+        return false;
+    }
+    let Some(data) = page.get_path(dl_dir).and_then(|path| fs::read(path).ok()) else {
+        return false;
+    };
+    Some(data.len() as i64) == page.byte_size && Some(sha256_hex(&data)) == page.checksum
+}
+
+/// Retries with exponential backoff. The permit is only held for the request itself, so
+/// pages waiting out a backoff don't block others.
+async fn fetch_page(
+    pool: &SqlitePool,
+    export_id: i64,
+    page: &ChapterPage,
+    dl_dir: &Path,
+) -> Result<(), AppError> {
+    let settings = page_download_settings();
+    let mut attempt = 0;
+    loop {
+        // Stopping isn't a download failure, so the page is left as it was
+        check_interrupted(export_id)?;
+        attempt += 1;
+        let result = {
+            let _permit = page_download_permits().acquire().await?;
+            dl_img(&page.url, dl_dir, page.page_index).await
+        };
+        match result {
+            Ok(mut downloaded) => {
+                // The page may have come back in a different format
+                if let Some(old_path) = page.get_path(dl_dir) {
+                    if page.filename.as_ref() != Some(&downloaded.filename) && old_path.exists() {
+                        fs::remove_file(old_path)?;
+                    }
+                }
+                downloaded.attempts = attempt as i64;
+                set_page_downloaded(pool, page.id, &downloaded).await?;
+                progress::advance(export_id, 1);
+                return Ok(());
+            }
+            Err(e) if attempt <= settings.retries => {
+                let delay = settings
+                    .backoff
+                    .saturating_mul(2u32.saturating_pow(attempt - 1))
+                    .min(MAX_BACKOFF);
+                println!(
+                    "Page {} of chapter {} failed (attempt {}), retrying in {:?}: {}",
+                    page.page_index + 1,
+                    page.chapter_id,
+                    attempt,
+                    delay,
+                    e
+                );
+                sleep(delay).await;
+            }
+            Err(e) => {
+                set_page_failed(pool, page.id, attempt as i64, &e.to_string()).await?;
+                // A failed page is still done with, the missing page policy deals with it
+                progress::advance(export_id, 1);
+                return Err(e);
+            }
+        }
+    }
 }
 
 pub async fn fetch_chapters_from_suwayomi(
+    pool: &SqlitePool,
+    export_id: i64,
     ids: &HashSet<i64>,
-) -> Result<(), AppError> {
-    join_all(ids.iter().map(|id| fetch_chapter(*id))).await;
-    Ok(())
+) -> Result<HashMap<i64, String>, AppError> {
+    let results = join_all(
+        ids.iter()
+            .map(|id| async move { (*id, fetch_chapter(pool, export_id, *id).await) }),
+    )
+    .await;
+    check_interrupted(export_id)?;
+    // Chapters left without pages are dealt with by the missing page policy
+    let mut failed = HashMap::new();
+    for (chapter, result) in results {
+        if let Err(e) = result {
+            log_export(
+                pool,
+                export_id,
+                ExportStep::FetchingFromSuwayomi,
+                LogLevel::Warning,
+                &format!("Chapter {} could not be fetched: {}", chapter, e),
+            )
+            .await?;
+            failed.insert(chapter, e.to_string());
+        }
+    }
+    Ok(failed)
 }
 
 #[derive(GraphQLQuery)]
@@ -287,7 +675,11 @@ pub async fn fetch_chapters_from_suwayomi(
     response_derives = "Debug,Clone"
 )]
 pub struct FetchChapterPages;
-pub async fn fetch_chapter(chapter: i64) -> Result<(), AppError> {
+pub async fn fetch_chapter(
+    pool: &SqlitePool,
+    export_id: i64,
+    chapter: i64,
+) -> Result<(), AppError> {
     let client = reqwest::Client::new();
     let dl_dirname = format!("{}", chapter);
     let dl_prefix = &env::var("CHAPTER_DL_PATH").unwrap_or("data/chapters".to_string());
@@ -295,31 +687,70 @@ pub async fn fetch_chapter(chapter: i64) -> Result<(), AppError> {
 
     println!("Fetching chapter {}", chapter);
 
-    let urls = match post_graphql::<FetchChapterPages, _>(
+    let response = post_graphql::<FetchChapterPages, _>(
         &client,
         join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?,
         fetch_chapter_pages::Variables { id: chapter },
     )
-    .await?
-    .data
-    {
-        Some(data) => data.fetch_chapter_pages.pages,
-        None => vec![],
+    .await?;
+    // Syncing an empty page list would wipe the manifest of a chapter that's still cached
+    let fetched = match response.data {
+        Some(data) => data.fetch_chapter_pages,
+        None => {
+            let errors = response
+                .errors
+                .unwrap_or_default()
+                .iter()
+                .map(|error| error.message.to_owned())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(eyre!(
+                "Suwayomi returned no pages for chapter {}: {}",
+                chapter,
+                errors
+            )
+            .into());
+        }
     };
-
-    if fs::read_dir(&dl_dir).is_ok_and(|r| r.count() == urls.len()) {
-        println!("Chapter {} already downloaded", chapter);
-        return Ok(());
+    if fetched.pages.is_empty() {
+        return Err(eyre!(
+            "Suwayomi returned an empty page list for chapter {}",
+            chapter
+        )
+        .into());
     }
+    let urls = fetched.pages;
+    let info = CachedChapterInfo {
+        manga_id: fetched.chapter.manga.id,
+        manga_title: fetched.chapter.manga.title,
+        chapter_name: fetched.chapter.name,
+    };
 
-    join_all(urls.iter().map(|img| dl_img(img, &dl_dir)))
+    let manifest = sync_chapter_manifest(pool, chapter, &urls).await?;
+    let missing: Vec<&ChapterPage> = manifest
+        .iter()
+        .filter(|page| !is_page_intact(page, &dl_dir))
+        .collect();
+    progress::add_total(export_id, manifest.len() as i64);
+    progress::advance(export_id, (manifest.len() - missing.len()) as i64);
+    if missing.is_empty() {
+        println!("Chapter {} already downloaded", chapter);
+    } else {
+        // Pages that still fail are marked in the manifest with their error
+        let failed = join_all(
+            missing
+                .iter()
+                .map(|page| fetch_page(pool, export_id, page, &dl_dir)),
+        )
         .await
         .iter()
         .filter(|r| r.is_err())
-        .for_each(|r| {
-            // TODO: log error in db
-            println!("Error fetching page: {:?}", r);
-        });
+        .count();
+        if failed > 0 {
+            println!("Chapter {}: {} pages failed to download", chapter, failed);
+        }
+    }
+    record_cached_chapter(pool, chapter, &info).await?;
     Ok(())
 }
 
@@ -340,7 +771,7 @@ pub async fn get_chapters_by_ids(
         &client,
         join_url(&env::var("SUWAYOMI_URL")?, "/api/graphql")?,
         chapters_by_ids::Variables {
-            ids: Some(ids.into_iter().cloned().collect()),
+            ids: Some(ids.iter().cloned().collect()),
         },
     )
     .await?
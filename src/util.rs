@@ -1,3 +1,8 @@
+use std::sync::OnceLock;
+
+use image::ImageFormat;
+use regex::Regex;
+use sha2::{Digest, Sha256};
 use url::Url;
 
 pub fn join_url(base_url: &str, path: &str) -> Result<Url, url::ParseError> {
@@ -6,3 +11,74 @@ pub fn join_url(base_url: &str, path: &str) -> Result<Url, url::ParseError> {
     Ok(joined_url)
 }
 
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Pulls a volume number out of chapter names like "Vol.3 Ch.12" or "Volume 3 Chapter 12"
+pub fn parse_volume_number(chapter_name: &str) -> Option<i64> {
+    static VOLUME: OnceLock<Regex> = OnceLock::new();
+    let re = VOLUME.get_or_init(|| Regex::new(r"(?i)\bvol(?:ume)?\.?\s*(\d+)").unwrap());
+    re.captures(chapter_name)?.get(1)?.as_str().parse().ok()
+}
+
+/// File extension for an image format. These double as the MIME subtype, which is why JPEG
+/// is `jpeg` rather than `jpg`.
+pub fn image_extension(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Jpeg => Some("jpeg"),
+        ImageFormat::Png => Some("png"),
+        ImageFormat::Gif => Some("gif"),
+        ImageFormat::WebP => Some("webp"),
+        ImageFormat::Bmp => Some("bmp"),
+        ImageFormat::Avif => Some("avif"),
+        _ => None,
+    }
+}
+
+/// Works out an image's extension from its magic bytes rather than trusting the server
+pub fn sniff_image_extension(data: &[u8]) -> Option<&'static str> {
+    image::guess_format(data).ok().and_then(image_extension)
+}
+
+/// Hex encoded SHA-256, used to spot corrupt downloads
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Reads an optional setting from the environment, falling back when it's unset or invalid
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Byte counts for people, e.g. 1.5 GB
+pub fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
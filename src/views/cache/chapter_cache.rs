@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{response::Redirect, Extension};
+use axum_extra::extract::Form;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    models::chapter_cache::{get_chapter_cache_size, get_manga_cache_usage, MangaCacheUsage},
+    services::chapter_cache::{get_chapter_cache_limit, purge_manga_chapters},
+    util::format_size,
+    AppError,
+};
+
+#[derive(Template)]
+#[template(path = "chapter-cache.html")]
+pub struct ChapterCache {
+    usage: Vec<MangaCacheUsage>,
+    total_size: i64,
+    limit: Option<i64>,
+}
+
+impl ChapterCache {
+    fn size(&self, bytes: &i64) -> String {
+        format_size(*bytes)
+    }
+}
+
+#[axum::debug_handler]
+pub async fn view_chapter_cache(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+) -> Result<ChapterCache, AppError> {
+    Ok(ChapterCache {
+        usage: get_manga_cache_usage(&pool).await?,
+        total_size: get_chapter_cache_size(&pool).await?,
+        limit: get_chapter_cache_limit(),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct PurgeMangaInput {
+    /// Empty for chapters whose manga isn't known
+    manga_id: Option<i64>,
+}
+
+#[axum::debug_handler]
+pub async fn post_purge_manga(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Form(data): Form<PurgeMangaInput>,
+) -> Result<Redirect, AppError> {
+    let evicted = purge_manga_chapters(&pool, data.manga_id).await?;
+    println!("purged {} chapters from the chapter cache", evicted);
+    Ok(Redirect::to("/cache"))
+}
@@ -0,0 +1,12 @@
+use axum::{
+    routing::{get, post},
+    Router,
+};
+
+mod chapter_cache;
+
+pub fn get_routes() -> axum::Router {
+    Router::new()
+        .route("/", get(chapter_cache::view_chapter_cache))
+        .route("/purge", post(chapter_cache::post_purge_manga))
+}
@@ -6,4 +6,4 @@ use crate::suwayomi::chapters_by_ids::ChaptersByIdsChaptersNodes;
 #[template(path = "components/chapter-table.html")]
 pub struct ChapterTable {
     pub chapters: Vec<ChaptersByIdsChaptersNodes>,
-}
\ No newline at end of file
+}
@@ -0,0 +1,75 @@
+use askama::Template;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{
+        export::ExportStep,
+        export_log::{get_export_logs, ExportLog, LogFilter, LogLevel, LOG_PAGE_SIZE},
+    },
+    AppError,
+};
+
+/// Query string of the log panel and the logs endpoint
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LogParams {
+    pub step: Option<ExportStep>,
+    pub level: Option<LogLevel>,
+    pub page: Option<i64>,
+}
+
+impl LogParams {
+    pub fn filter(&self) -> LogFilter {
+        LogFilter {
+            step: self.step,
+            level: self.level,
+        }
+    }
+
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "components/log-panel.html")]
+pub struct LogPanel {
+    pub export_id: i64,
+    pub logs: Vec<ExportLog>,
+    pub params: LogParams,
+    pub total: i64,
+}
+
+impl LogPanel {
+    pub async fn load(
+        pool: &SqlitePool,
+        export_id: i64,
+        params: LogParams,
+    ) -> Result<Self, AppError> {
+        let (logs, total) =
+            get_export_logs(pool, export_id, &params.filter(), params.page()).await?;
+        Ok(LogPanel {
+            export_id,
+            logs,
+            params,
+            total,
+        })
+    }
+
+    fn page_count(&self) -> i64 {
+        ((self.total + LOG_PAGE_SIZE - 1) / LOG_PAGE_SIZE).max(1)
+    }
+
+    /// Link to another page of the logs that keeps the filters
+    fn page_link(&self, page: i64) -> String {
+        let params = LogParams {
+            page: Some(page),
+            ..self.params.clone()
+        };
+        format!(
+            "/export/{}?{}#logs",
+            self.export_id,
+            serde_html_form::to_string(&params).unwrap_or_default()
+        )
+    }
+}
@@ -1 +1,2 @@
-pub mod chapter_table;
\ No newline at end of file
+pub mod chapter_table;
+pub mod log_panel;
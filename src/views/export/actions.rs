@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, response::Redirect, Extension};
+use axum_extra::extract::Form;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    services::exporter::{cancel_export, delete_export, pause_export, resume_export, retry_export},
+    AppError,
+};
+
+#[axum::debug_handler]
+pub async fn post_retry_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+) -> Result<Redirect, AppError> {
+    retry_export(pool, id).await?;
+    Ok(Redirect::to(&format!("/export/{}", id)))
+}
+
+#[axum::debug_handler]
+pub async fn post_pause_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+) -> Result<Redirect, AppError> {
+    pause_export(pool, id).await?;
+    Ok(Redirect::to(&format!("/export/{}", id)))
+}
+
+#[axum::debug_handler]
+pub async fn post_resume_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+) -> Result<Redirect, AppError> {
+    resume_export(pool, id).await?;
+    Ok(Redirect::to(&format!("/export/{}", id)))
+}
+
+#[axum::debug_handler]
+pub async fn post_cancel_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+) -> Result<Redirect, AppError> {
+    cancel_export(pool, id).await?;
+    Ok(Redirect::to(&format!("/export/{}", id)))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteExportInput {
+    #[serde(default)]
+    purge_chapters: bool,
+}
+
+#[axum::debug_handler]
+pub async fn post_delete_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+    Form(data): Form<DeleteExportInput>,
+) -> Result<Redirect, AppError> {
+    delete_export(pool, id, data.purge_chapters).await?;
+    Ok(Redirect::to("/exports"))
+}
@@ -14,7 +14,9 @@ use sqlx::SqlitePool;
 
 use crate::{
     models::export::{
-        get_export_and_chapters_by_id, set_export_config, Export, ExportFormat, ExportState,
+        get_export_and_chapters_by_id, set_export_config, CbzPageFormat, CoverSource,
+        DeviceProfile, EpubLayout, Export, ExportConfig, ExportFormat, ExportState,
+        MissingPagePolicy, ReadingDirection, SplitMode, SpreadMode,
     },
     services::exporter::begin_export,
     suwayomi::get_chapters_by_ids,
@@ -34,7 +36,7 @@ pub async fn view_configure_book(
     Extension(pool): Extension<Arc<SqlitePool>>,
     Path(id): Path<i64>,
 ) -> Result<Response, AppError> {
-    let (export, chapters) = match get_export_and_chapters_by_id(&*pool, id).await? {
+    let (export, chapters) = match get_export_and_chapters_by_id(&pool, id).await? {
         Some(export_and_chapters) => Ok(export_and_chapters),
         None => Err(AppError(eyre!("Export not found"))),
     }?;
@@ -55,11 +57,39 @@ pub async fn view_configure_book(
     .into_response())
 }
 
+/// Pixels, more than this would keep most borders on pages at device resolution
+const MAX_CROP_SAFETY_MARGIN: i64 = 200;
+
 #[derive(Deserialize)]
 pub struct ConfigureExportInput {
     title: String,
     author: String,
     format: ExportFormat,
+    epub_layout: EpubLayout,
+    reading_direction: ReadingDirection,
+    cover_source: CoverSource,
+    cover_chapter_id: Option<i64>,
+    cover_page: Option<i64>,
+    split_mode: SplitMode,
+    split_value: Option<i64>,
+    device_profile: DeviceProfile,
+    device_width: Option<i64>,
+    device_height: Option<i64>,
+    #[serde(default)]
+    grayscale: bool,
+    gamma: Option<f64>,
+    #[serde(default)]
+    dither: bool,
+    spread_mode: SpreadMode,
+    #[serde(default)]
+    crop_margins: bool,
+    crop_safety_margin: Option<i64>,
+    crop_max_percent: Option<i64>,
+    cbz_page_format: CbzPageFormat,
+    #[serde(default)]
+    webtoon: bool,
+    missing_page_policy: MissingPagePolicy,
+    priority: Option<i64>,
     action: String,
 }
 
@@ -68,7 +98,41 @@ pub async fn post_configure_export(
     Path(id): Path<i64>,
     Form(data): Form<ConfigureExportInput>,
 ) -> Result<Response, AppError> {
-    set_export_config(&pool, id, &data.title, &data.author, data.format).await?;
+    if let Some(gamma) = data.gamma {
+        // The curve flattens every shade to white or black at 0 and below
+        if !gamma.is_finite() || gamma <= 0.0 {
+            return Err(AppError(eyre!("Gamma has to be a positive number")));
+        }
+    }
+    let config = ExportConfig {
+        title: data.title,
+        author: data.author,
+        format: data.format,
+        epub_layout: data.epub_layout,
+        reading_direction: data.reading_direction,
+        cover_source: data.cover_source,
+        cover_chapter_id: data.cover_chapter_id,
+        cover_page: data.cover_page,
+        split_mode: data.split_mode,
+        split_value: data.split_value,
+        device_profile: data.device_profile,
+        device_width: data.device_width,
+        device_height: data.device_height,
+        grayscale: data.grayscale,
+        gamma: data.gamma,
+        dither: data.dither,
+        spread_mode: data.spread_mode,
+        crop_margins: data.crop_margins,
+        crop_safety_margin: data
+            .crop_safety_margin
+            .map(|margin| margin.clamp(0, MAX_CROP_SAFETY_MARGIN)),
+        crop_max_percent: data.crop_max_percent.map(|percent| percent.clamp(0, 100)),
+        cbz_page_format: data.cbz_page_format,
+        webtoon: data.webtoon,
+        missing_page_policy: data.missing_page_policy,
+        priority: data.priority.unwrap_or(0),
+    };
+    set_export_config(&pool, id, &config).await?;
     // do this to render template
     // view_configure_book(Extension(pool), Path(id)).await
     if data.action == "save" {
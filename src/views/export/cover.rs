@@ -0,0 +1,60 @@
+use std::{io::Cursor, path::Path as FsPath, sync::Arc};
+
+use axum::{
+    extract::{Multipart, Path},
+    response::Redirect,
+    Extension,
+};
+use eyre::eyre;
+use sqlx::SqlitePool;
+
+use crate::{
+    models::export::{get_cover_base_dir, get_export_by_id, set_export_cover_upload, ExportState},
+    util::sniff_image_extension,
+    AppError,
+};
+
+#[axum::debug_handler]
+pub async fn post_cover_upload(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+    mut multipart: Multipart,
+) -> Result<Redirect, AppError> {
+    let export = match get_export_by_id(&pool, id).await? {
+        Some(export) => export,
+        None => return Err(eyre!("Export not found").into()),
+    };
+    if export.state != ExportState::Draft {
+        return Err(eyre!("Export is not in draft state").into());
+    }
+
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() != Some("cover") {
+            continue;
+        }
+        let data = field.bytes().await?;
+        if data.is_empty() {
+            return Err(eyre!("No cover uploaded").into());
+        }
+        // The browser's content type is only a guess, so the format comes from the data itself
+        let extension = match sniff_image_extension(&data) {
+            Some(extension @ ("jpeg" | "png" | "gif" | "webp")) => extension,
+            _ => return Err(eyre!("Cover must be a JPEG, PNG, GIF or WebP image").into()),
+        };
+        if image::ImageReader::new(Cursor::new(&data))
+            .with_guessed_format()?
+            .into_dimensions()
+            .is_err()
+        {
+            return Err(eyre!("Cover image is corrupt").into());
+        }
+
+        let cover_base_dir = get_cover_base_dir();
+        std::fs::create_dir_all(&cover_base_dir)?;
+        let cover_path = FsPath::new(&cover_base_dir).join(format!("{}.{}", id, extension));
+        std::fs::write(&cover_path, &data)?;
+        set_export_cover_upload(&pool, id, &cover_path.to_string_lossy()).await?;
+    }
+
+    Ok(Redirect::to(&format!("/export/{}/configure", id)))
+}
@@ -2,13 +2,22 @@ use std::sync::Arc;
 
 use askama::Template;
 use axum::{debug_handler, extract::Path, Extension};
+use axum_extra::extract::Query;
 use eyre::eyre;
 use sqlx::SqlitePool;
 
 use crate::{
-    models::export::{get_export_and_chapters_by_id, Export},
+    models::{
+        export::{get_export_and_chapters_by_id, Export},
+        export_job::get_export_queue_positions,
+        export_part::{get_export_parts, ExportPart},
+        missing_page::{get_missing_pages, MissingPage},
+    },
     suwayomi::get_chapters_by_ids,
-    views::components::chapter_table::ChapterTable,
+    views::components::{
+        chapter_table::ChapterTable,
+        log_panel::{LogPanel, LogParams},
+    },
     AppError,
 };
 
@@ -16,15 +25,20 @@ use crate::{
 #[template(path = "export-details.html")]
 pub struct ExportDetails {
     export: Export,
+    parts: Vec<ExportPart>,
+    /// Paired with the chapter's name
+    missing_pages: Vec<(String, MissingPage)>,
+    queue_position: Option<i64>,
     chapter_table: ChapterTable,
+    log_panel: LogPanel,
 }
 
-// TODO task log
 // TODO status from tasks table
 #[debug_handler]
 pub async fn view_book_details(
     Extension(pool): Extension<Arc<SqlitePool>>,
     Path(id): Path<i64>,
+    Query(log_params): Query<LogParams>,
 ) -> Result<ExportDetails, AppError> {
     let (export, chapter_ids) = match get_export_and_chapters_by_id(&pool, id).await? {
         Some(book) => book,
@@ -34,9 +48,30 @@ pub async fn view_book_details(
         Some(chapters) => chapters.nodes,
         None => return Err(eyre!("Chapters not found").into()),
     };
+    let parts = get_export_parts(&pool, id).await?;
+    let missing_pages = get_missing_pages(&pool, id)
+        .await?
+        .into_iter()
+        .map(|page| {
+            let name = chapters
+                .iter()
+                .find(|chapter| chapter.id == page.chapter_id)
+                .map_or_else(
+                    || page.chapter_id.to_string(),
+                    |chapter| chapter.name.clone(),
+                );
+            (name, page)
+        })
+        .collect();
+    let queue_position = get_export_queue_positions(&pool).await?.get(&id).copied();
+    let log_panel = LogPanel::load(&pool, id, log_params).await?;
     let template = ExportDetails {
         export,
+        parts,
+        missing_pages,
+        queue_position,
         chapter_table: ChapterTable { chapters },
+        log_panel,
     };
 
     Ok(template)
@@ -4,21 +4,23 @@ use askama_axum::IntoResponse;
 use axum::{
     debug_handler,
     extract::{Path, Request},
+    response::{Redirect, Response},
     Extension,
 };
 use sqlx::SqlitePool;
 use tower::ServiceExt;
 use tower_http::services::ServeFile;
 
-use crate::models::export::{get_export_by_id, ExportState};
+use crate::models::{
+    export::{get_export_by_id, Export, ExportState},
+    export_part::{get_export_part, get_export_parts},
+};
 
-#[debug_handler]
-pub async fn serve_export(
-    Path(id): Path<i64>,
-    Extension(pool): Extension<Arc<SqlitePool>>,
-    request: Request,
-) -> Result<impl IntoResponse, (axum::http::StatusCode, String)> {
-    let maybe_export = match get_export_by_id(&*pool, id).await {
+async fn get_completed_export(
+    pool: &SqlitePool,
+    id: i64,
+) -> Result<Export, (axum::http::StatusCode, String)> {
+    let maybe_export = match get_export_by_id(pool, id).await {
         Ok(export) => Ok(export),
         Err(e) => Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }?;
@@ -32,8 +34,47 @@ pub async fn serve_export(
             "Export not completed".into(),
         ));
     }
-    dbg!("request for export", &export);
-    let path = export.get_path();
+    Ok(export)
+}
+
+/// Serves the file of a single file export. Split exports have one link per part on the
+/// details page instead, so send the user there.
+#[debug_handler]
+pub async fn serve_export(
+    Path(id): Path<i64>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    request: Request,
+) -> Result<Response, (axum::http::StatusCode, String)> {
+    let export = get_completed_export(&pool, id).await?;
+    let parts = match get_export_parts(&pool, id).await {
+        Ok(parts) => Ok(parts),
+        Err(e) => Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }?;
+    let path = match parts.as_slice() {
+        // exports completed before parts were recorded
+        [] => export.get_path(),
+        [part] => part.get_path(),
+        _ => return Ok(Redirect::to(&format!("/export/{}", id)).into_response()),
+    };
     dbg!("serving file", &path);
+    Ok(ServeFile::new(path).oneshot(request).await.into_response())
+}
+
+#[debug_handler]
+pub async fn serve_export_part(
+    Path((id, part)): Path<(i64, i64)>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    request: Request,
+) -> Result<impl IntoResponse, (axum::http::StatusCode, String)> {
+    get_completed_export(&pool, id).await?;
+    let maybe_part = match get_export_part(&pool, id, part).await {
+        Ok(part) => Ok(part),
+        Err(e) => Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }?;
+    let part = match maybe_part {
+        Some(part) => Ok(part),
+        None => Err((axum::http::StatusCode::NOT_FOUND, "Part not found".into())),
+    }?;
+    let path = part.get_path();
     Ok(ServeFile::new(path).oneshot(request).await)
 }
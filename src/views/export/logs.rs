@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, Extension, Json};
+use axum_extra::extract::Query;
+use eyre::eyre;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{
+        export::get_export_by_id,
+        export_log::{get_export_logs, ExportLog, LOG_PAGE_SIZE},
+    },
+    views::components::log_panel::LogParams,
+    AppError,
+};
+
+#[derive(Serialize)]
+pub struct ExportLogs {
+    logs: Vec<ExportLog>,
+    page: i64,
+    page_size: i64,
+    total: i64,
+}
+
+/// Same logs and filters as the details page, newest first
+#[axum::debug_handler]
+pub async fn get_export_logs_json(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+    Query(params): Query<LogParams>,
+) -> Result<Json<ExportLogs>, AppError> {
+    get_export_by_id(&pool, id)
+        .await?
+        .ok_or(eyre!("Export not found"))?;
+    let page = params.page();
+    let (logs, total) = get_export_logs(&pool, id, &params.filter(), page).await?;
+    Ok(Json(ExportLogs {
+        logs,
+        page,
+        page_size: LOG_PAGE_SIZE,
+        total,
+    }))
+}
@@ -1,8 +1,15 @@
-use axum::{routing::get, Router};
+use axum::{
+    routing::{get, post},
+    Router,
+};
 
+mod actions;
 pub mod configure;
+mod cover;
 mod details;
 mod download;
+mod logs;
+mod progress;
 
 pub fn get_routes() -> axum::Router {
     Router::new()
@@ -11,5 +18,14 @@ pub fn get_routes() -> axum::Router {
             "/:id/configure",
             get(configure::view_configure_book).post(configure::post_configure_export),
         )
+        .route("/:id/cover", post(cover::post_cover_upload))
         .route("/:id/download", get(download::serve_export))
+        .route("/:id/download/:part", get(download::serve_export_part))
+        .route("/:id/logs.json", get(logs::get_export_logs_json))
+        .route("/:id/progress", get(progress::view_export_progress))
+        .route("/:id/retry", post(actions::post_retry_export))
+        .route("/:id/pause", post(actions::post_pause_export))
+        .route("/:id/resume", post(actions::post_resume_export))
+        .route("/:id/cancel", post(actions::post_cancel_export))
+        .route("/:id/delete", post(actions::post_delete_export))
 }
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    response::sse::{Event, Sse},
+    Extension,
+};
+use eyre::eyre;
+use futures::Stream;
+use sqlx::SqlitePool;
+
+use crate::{
+    models::export::get_export_by_id,
+    services::progress::{get_progress, ProgressUpdate},
+    views::exports::progress::progress_events,
+    AppError,
+};
+
+/// Progress of one export, starting with where it's at now. Exports that aren't running
+/// start from what was last saved.
+#[axum::debug_handler]
+pub async fn view_export_progress(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(id): Path<i64>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let export = get_export_by_id(&pool, id)
+        .await?
+        .ok_or(eyre!("Export not found"))?;
+    let current = get_progress(id).unwrap_or_else(|| {
+        ProgressUpdate::new(
+            id,
+            export.state,
+            export.step,
+            export.progress,
+            export.progress_total,
+        )
+    });
+    Ok(progress_events(vec![current], Some(id)))
+}
@@ -9,7 +9,7 @@ use serde::Deserialize;
 use sqlx::SqlitePool;
 
 use crate::{
-    models::export::{create_export, set_chapters_for_export},
+    models::export::{create_export, set_chapters_for_export, ReadingDirection},
     suwayomi::{self, specific_manga_chapters::SpecificMangaChaptersMangaChaptersNodes},
     AppError,
 };
@@ -68,8 +68,12 @@ pub async fn post_chapter_select(
     }
     let manga = suwayomi::get_manga_by_id(params.manga_id).await?;
     let author = manga.author.unwrap_or("Unknown".to_string());
+    let reading_direction = ReadingDirection::default_for_manga(
+        manga.source.as_ref().map(|source| source.lang.as_str()),
+        &manga.genre,
+    );
 
-    let export = create_export(&*pool, &manga.title, &author).await?;
+    let export = create_export(&pool, &manga.title, &author, reading_direction).await?;
     set_chapters_for_export(&pool, export, params.chapter_id).await?;
     Ok(Redirect::to(&format!("/export/{}/configure", export)))
 }
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use axum::{response::Redirect, Extension};
+use axum_extra::extract::Form;
+use eyre::eyre;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{services::exporter::delete_export, AppError};
+
+#[derive(Deserialize)]
+pub struct DeleteExportsInput {
+    #[serde(default)]
+    export_id: Vec<i64>,
+    #[serde(default)]
+    purge_chapters: bool,
+}
+
+/// Deletes every selected export it can. Exports that couldn't be deleted, like running
+/// ones, are listed in the error.
+#[axum::debug_handler]
+pub async fn post_delete_exports(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Form(data): Form<DeleteExportsInput>,
+) -> Result<Redirect, AppError> {
+    let mut failed = Vec::new();
+    for id in data.export_id {
+        if let Err(e) = delete_export(pool.clone(), id, data.purge_chapters).await {
+            failed.push(format!("export {}: {}", id, e));
+        }
+    }
+    if !failed.is_empty() {
+        return Err(eyre!("Couldn't delete {}", failed.join(", ")).into());
+    }
+    Ok(Redirect::to("/exports"))
+}
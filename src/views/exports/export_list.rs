@@ -1,11 +1,14 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use askama::Template;
 use axum::Extension;
 use sqlx::SqlitePool;
 
 use crate::{
-    models::export::{get_export_list, Export},
+    models::{
+        export::{get_export_list, Export},
+        export_job::get_export_queue_positions,
+    },
     AppError,
 };
 
@@ -13,12 +16,24 @@ use crate::{
 #[template(path = "export-list.html")]
 pub struct ExportList {
     exports: Vec<Export>,
+    queue_positions: HashMap<i64, i64>,
+}
+
+impl ExportList {
+    /// None once the export has left the queue
+    fn queue_position(&self, id: &i64) -> Option<i64> {
+        self.queue_positions.get(id).copied()
+    }
 }
 
 #[axum::debug_handler]
 pub async fn view_export_list(
     Extension(pool): Extension<Arc<SqlitePool>>,
 ) -> Result<ExportList, AppError> {
-    let exports = get_export_list(&*pool).await?;
-    Ok(ExportList { exports })
+    let exports = get_export_list(&pool).await?;
+    let queue_positions = get_export_queue_positions(&pool).await?;
+    Ok(ExportList {
+        exports,
+        queue_positions,
+    })
 }
@@ -1,7 +1,15 @@
-use axum::{routing::get, Router};
+use axum::{
+    routing::{get, post},
+    Router,
+};
 
+mod actions;
 mod export_list;
+pub mod progress;
 
 pub fn get_routes() -> axum::Router {
-    Router::new().route("/", get(export_list::view_export_list))
+    Router::new()
+        .route("/", get(export_list::view_export_list))
+        .route("/progress", get(progress::view_exports_progress))
+        .route("/delete", post(actions::post_delete_exports))
 }
@@ -0,0 +1,36 @@
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::{stream, Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::services::progress::{subscribe, ProgressUpdate};
+
+/// Streams progress updates as JSON events, starting with `initial`. Only updates for
+/// `export_id` are sent when one is given.
+pub fn progress_events(
+    initial: Vec<ProgressUpdate>,
+    export_id: Option<i64>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let receiver = subscribe();
+    let updates = stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(update) if export_id.is_none_or(|id| id == update.export_id) => {
+                    return Some((update, receiver))
+                }
+                // A slow page skips ahead, the next update has the full counts anyway
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::iter(initial)
+        .chain(updates)
+        .map(|update| Event::default().json_data(update));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Progress of every export, for the list page
+#[axum::debug_handler]
+pub async fn view_exports_progress() -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    progress_events(vec![], None)
+}
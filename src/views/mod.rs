@@ -1,4 +1,5 @@
+pub mod cache;
+pub mod components;
 pub mod export;
 pub mod export_new;
 pub mod exports;
-pub mod components;
\ No newline at end of file